@@ -0,0 +1,1038 @@
+//! Sproc configuration checks (`sproc check`)
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    ops::Range,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use toml_edit::{ImDocument, Item, Key};
+
+use crate::layers::{Layers, Origin};
+use crate::model::{
+    self, RestartPolicy, ServerConfiguration, Service, ServiceType, ServicesConfiguration,
+};
+use crate::{shell, timers, unix, vars};
+
+/// Keys accepted at the top level of a configuration file
+const CONFIG_KEYS: &[&str] = &[
+    "source", "inherit", "server", "vars", "services", "groups", "overlay",
+];
+
+/// Keys accepted in `[overlay.<host>]`
+const OVERLAY_KEYS: &[&str] = &["server", "vars", "services", "groups"];
+
+/// Keys accepted in `[server]`
+const SERVER_KEYS: &[&str] = &["port", "key", "registry", "metrics", "socket"];
+
+/// Keys accepted in `[server.metrics]`
+const METRICS_KEYS: &[&str] = &["interval", "history"];
+
+/// Keys accepted in `[server.registry]`
+const REGISTRY_KEYS: &[&str] = &["enabled", "description", "name", "nav"];
+
+/// Keys accepted in `[services.<name>]`
+const SERVICE_KEYS: &[&str] = &[
+    "type",
+    "command",
+    "args",
+    "shell",
+    "working_directory",
+    "environment",
+    "env_file",
+    "restart",
+    "restart_delay",
+    "restart_delay_max",
+    "max_restarts",
+    "restart_window",
+    "autostart",
+    "depends_on",
+    "stop_signal",
+    "stop_timeout",
+    "pre_start",
+    "post_start",
+    "pre_stop",
+    "post_stop",
+    "hook_timeout",
+    "watch",
+    "watch_ignore",
+    "watch_debounce",
+    "health",
+    "logs",
+    "limits",
+    "user",
+    "group",
+    "groups",
+    "schedule",
+    "skip_overlapping",
+    "tags",
+    "replicas",
+    "base_port",
+    "metadata",
+];
+
+/// Keys accepted in `[services.<name>.health]`
+const HEALTH_KEYS: &[&str] = &["type", "target", "interval", "timeout", "threshold"];
+
+/// Keys accepted in `[services.<name>.logs]`
+const LOGS_KEYS: &[&str] = &["max_size", "max_files"];
+
+/// Keys accepted in `[services.<name>.limits]`
+const LIMITS_KEYS: &[&str] = &["memory", "open_files", "cpu", "processes", "core"];
+
+/// Keys accepted in `[services.<name>.metadata]`
+const METADATA_KEYS: &[&str] = &["owner", "repository", "description", "license", "build"];
+
+/// Get the keys accepted in the table at `path` (`None` if any key is accepted)
+fn known_keys(path: &[&str]) -> Option<&'static [&'static str]> {
+    match path {
+        [] => Some(CONFIG_KEYS),
+        ["overlay", _] => Some(OVERLAY_KEYS),
+        ["overlay", _, rest @ ..] if !rest.is_empty() => known_keys(rest),
+        ["server"] => Some(SERVER_KEYS),
+        ["server", "registry"] => Some(REGISTRY_KEYS),
+        ["server", "metrics"] => Some(METRICS_KEYS),
+        ["services", _] => Some(SERVICE_KEYS),
+        ["services", _, "health"] => Some(HEALTH_KEYS),
+        ["services", _, "logs"] => Some(LOGS_KEYS),
+        ["services", _, "limits"] => Some(LIMITS_KEYS),
+        ["services", _, "metadata"] => Some(METADATA_KEYS),
+        _ => None,
+    }
+}
+
+/// How serious a [`Diagnostic`] is
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// The configuration is invalid, or a service can't be started
+    Error,
+    /// The configuration works, but probably not as intended
+    Warning,
+}
+
+/// A problem found in a configuration file
+#[derive(Serialize, Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file,
+            self.line,
+            self.column,
+            match self.level {
+                Level::Error => "error",
+                Level::Warning => "warning",
+            },
+            self.message
+        )
+    }
+}
+
+/// A configuration file being checked
+struct SourceFile {
+    path: String,
+    contents: String,
+    document: Option<ImDocument<String>>,
+}
+
+impl SourceFile {
+    /// Create a [`Diagnostic`] at the given byte span of the file
+    fn diagnostic(&self, level: Level, span: Option<Range<usize>>, message: String) -> Diagnostic {
+        let offset = span.map(|s| s.start).unwrap_or(0).min(self.contents.len());
+        let before = &self.contents[..offset];
+
+        Diagnostic {
+            level,
+            file: self.path.clone(),
+            line: before.matches('\n').count() + 1,
+            column: before[before.rfind('\n').map(|i| i + 1).unwrap_or(0)..]
+                .chars()
+                .count()
+                + 1,
+            message,
+        }
+    }
+
+    /// Get the span of the deepest key of `path` that exists in the file
+    fn locate(&self, path: &[&str]) -> Option<Range<usize>> {
+        let mut item = self.document.as_ref()?.as_item();
+        let mut span = None;
+
+        for key in path {
+            let (k, value) = match item.as_table_like().and_then(|t| t.get_key_value(key)) {
+                Some(kv) => kv,
+                None => break,
+            };
+
+            span = k.span().or(value.span()).or(span);
+            item = value;
+        }
+
+        span
+    }
+
+    /// Get the span of the value at `path`, if it is written inline (not as a table of its own)
+    fn locate_value(&self, path: &[&str]) -> Option<Range<usize>> {
+        let mut item = self.document.as_ref()?.as_item();
+
+        for key in path {
+            item = item.as_table_like()?.get(key)?;
+        }
+
+        item.as_value()?.span()
+    }
+}
+
+/// Collects every [`Diagnostic`] found while checking
+struct Checker {
+    files: Vec<SourceFile>,
+    diagnostics: Vec<Diagnostic>,
+    /// Where every value of the merged configuration was set
+    origins: BTreeMap<Vec<String>, Origin>,
+    /// If a file couldn't be parsed (the merged configuration isn't checked)
+    invalid: bool,
+    /// Services of the merged configuration that couldn't be deserialized (they aren't checked,
+    /// but other services can still refer to them)
+    broken: HashSet<String>,
+}
+
+impl Checker {
+    /// Report a problem in a file (`file` is an index into `files`)
+    fn report(&mut self, file: usize, level: Level, path: &[&str], message: String) {
+        let span = self.files[file].locate(path);
+        let diagnostic = self.files[file].diagnostic(level, span, message);
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Report a problem with a value of the merged configuration, in the file (and overlay) the
+    /// value was set in
+    ///
+    /// Values that aren't set are reported where the closest table containing them was set.
+    fn report_value(&mut self, level: Level, path: &[&str], message: String) {
+        let origin = (1..=path.len()).rev().find_map(|len| {
+            let prefix: Vec<String> = path[..len].iter().map(|k| k.to_string()).collect();
+
+            self.origins
+                .range(prefix.clone()..)
+                .next()
+                .filter(|(k, _)| k.starts_with(&prefix))
+                .map(|(_, o)| o.clone())
+        });
+
+        let origin = match origin {
+            Some(o) => o,
+            None => return self.report(0, level, path, message),
+        };
+
+        let file = self
+            .files
+            .iter()
+            .position(|f| f.path == origin.file)
+            .unwrap_or(0);
+
+        match origin.overlay {
+            Some(ref host) => {
+                let mut full = vec!["overlay", host.as_str()];
+                full.extend_from_slice(path);
+                self.report(file, level, &full, message)
+            }
+            None => self.report(file, level, path, message),
+        }
+    }
+
+    /// Read and parse a file, checking its syntax, keys and types
+    ///
+    /// Returns the file's index. Files don't need to be complete on their own (fields can be set
+    /// by other layers), so missing fields are only checked in the merged configuration.
+    fn load(&mut self, path: &str, inherited: bool) -> usize {
+        let contents = std::fs::read_to_string(path).unwrap_or_default();
+
+        let mut file = SourceFile {
+            path: path.to_string(),
+            contents,
+            document: None,
+        };
+
+        // check syntax
+        match ImDocument::parse(file.contents.clone()) {
+            Ok(d) => file.document = Some(d),
+            Err(e) => {
+                let diagnostic =
+                    file.diagnostic(Level::Error, e.span(), e.message().trim().to_string());
+                self.diagnostics.push(diagnostic);
+            }
+        }
+
+        self.files.push(file);
+        let index = self.files.len() - 1;
+
+        if self.files[index].document.is_none() {
+            self.invalid = true;
+            return index;
+        }
+
+        // check keys
+        let mut unknown = Vec::new();
+
+        if let Some(ref document) = self.files[index].document {
+            find_unknown_keys(document.as_item(), &mut Vec::new(), &mut unknown);
+        }
+
+        for (path, key) in unknown {
+            let path: Vec<&str> = path.iter().map(|k| k.as_str()).collect();
+            self.report(index, Level::Error, &path, format!("unknown key \"{key}\""));
+        }
+
+        if inherited && self.files[index].locate(&["inherit"]).is_some() {
+            self.report(
+                index,
+                Level::Error,
+                &["inherit"],
+                "inherited files cannot inherit other files".to_string(),
+            );
+        }
+
+        // check types
+        let value = match toml::from_str::<toml::Table>(&self.files[index].contents) {
+            Ok(v) => v,
+            Err(_) => return index,
+        };
+
+        self.check_config_types(index, &value, &[]);
+
+        if let Some(toml::Value::Table(overlays)) = value.get("overlay") {
+            for host in overlays.keys() {
+                self.check_config_types(index, &value, &["overlay", host]);
+            }
+        }
+
+        index
+    }
+
+    /// Check the types of a configuration (the root of a file, or an overlay) at `path`
+    ///
+    /// The server and every service are checked on their own, so one invalid value doesn't
+    /// hide the others.
+    fn check_config_types(&mut self, file: usize, value: &toml::Table, path: &[&str]) {
+        let table = match lookup(value, path) {
+            Some(t) => t,
+            None => return,
+        };
+
+        let at = |keys: &[&str]| -> Vec<String> {
+            path.iter().chain(keys).map(|k| k.to_string()).collect()
+        };
+
+        // tables that are checked on their own (overlays are checked by the caller)
+        let mut skip = Vec::new();
+
+        for key in ["server", "services", "overlay"] {
+            if table.get(key).is_some_and(|v| v.is_table()) {
+                skip.push(key);
+            }
+        }
+
+        if skip.contains(&"server") {
+            self.check_types::<ServerConfiguration>(file, value, &at(&["server"]), &[]);
+        }
+
+        if let Some(toml::Value::Table(services)) = table.get("services") {
+            for name in services.keys() {
+                self.check_types::<Service>(file, value, &at(&["services", name]), &[]);
+            }
+        }
+
+        self.check_types::<ServicesConfiguration>(file, value, &at(&[]), &skip);
+    }
+
+    /// Check the type of every value of the table at `path` (except the `skip` keys), as the
+    /// fields of `T`
+    ///
+    /// Values are deserialized one at a time, missing fields are only checked in the merged
+    /// configuration.
+    fn check_types<T: DeserializeOwned>(
+        &mut self,
+        file: usize,
+        value: &toml::Table,
+        path: &[String],
+        skip: &[&str],
+    ) {
+        let path: Vec<&str> = path.iter().map(|k| k.as_str()).collect();
+
+        let table = match lookup(value, &path) {
+            Some(t) => t,
+            None => {
+                // values that should be tables
+                let field = path
+                    .split_last()
+                    .and_then(|(key, parent)| lookup(value, parent)?.get(*key));
+
+                if let Some(Err(e)) = field.map(|f| f.clone().try_into::<T>()) {
+                    self.report(file, Level::Error, &path, e.message().trim().to_string());
+                }
+
+                return;
+            }
+        };
+
+        for (key, field) in table {
+            if skip.contains(&key.as_str()) {
+                continue;
+            }
+
+            let e = match field_error::<T>(key, field) {
+                Some(e) => e,
+                None => continue,
+            };
+
+            let mut at = path.clone();
+            at.push(key);
+
+            // values written inline are parsed again from the file, so the error points at the
+            // part of the value that is invalid
+            let source = &self.files[file];
+            let span = source.locate_value(&at).and_then(|span| {
+                let prefix = format!("{} = ", Key::new(key));
+                let text = format!("{prefix}{}", &source.contents[span.clone()]);
+
+                let inner = toml::from_str::<T>(&text).err()?.span()?;
+                let start = inner.start.checked_sub(prefix.len())?;
+                let end = inner.end.checked_sub(prefix.len())?;
+
+                Some(span.start + start..span.start + end)
+            });
+
+            let span = span.or(source.locate(&at));
+            let diagnostic = source.diagnostic(Level::Error, span, e.message().trim().to_string());
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Check a single (merged) service
+    fn check_service(
+        &mut self,
+        name: &str,
+        service: &Service,
+        config: &ServicesConfiguration,
+        cycles: &mut HashSet<String>,
+    ) {
+        let at = |key: &'static str| -> Vec<&str> { vec!["services", name, key] };
+
+        if !model::is_service_name(name) {
+            self.report_value(
+                Level::Error,
+                &["services", name],
+                "service names can't contain \"#\" (it's used in the names of replicas)"
+                    .to_string(),
+            );
+        }
+
+        // dependencies
+        let mut missing = false;
+
+        for dependency in &service.depends_on {
+            if self.broken.contains(dependency) {
+                // already reported, but the start order can't be checked without it
+                missing = true;
+            } else if config.service(dependency).is_none() {
+                missing = true;
+                self.report_value(
+                    Level::Error,
+                    &at("depends_on"),
+                    format!("depends on service \"{dependency}\", which does not exist"),
+                );
+            }
+        }
+
+        // templates are only started as instances (which can't depend on themselves)
+        if !missing && !model::is_template(name) {
+            if let Err(e) = config.start_order(&[name.to_string()]) {
+                // every service in a cycle would report it, so it's only reported once
+                let message = e.to_string();
+                let mut members: Vec<&str> = match message.rsplit_once('(') {
+                    Some((_, cycle)) => cycle.trim_end_matches(')').split(" -> ").collect(),
+                    None => Vec::new(),
+                };
+
+                members.sort();
+                members.dedup();
+
+                if cycles.insert(members.join(" ")) {
+                    self.report_value(Level::Error, &at("depends_on"), message);
+                }
+            }
+        }
+
+        // restart policy
+        if (service.r#type != ServiceType::Service) && (service.restart != RestartPolicy::Never) {
+            self.report_value(
+                Level::Warning,
+                &at("restart"),
+                "restart policies are only used by services with type \"Service\"".to_string(),
+            );
+        }
+
+        if service.restart_delay > service.restart_delay_max {
+            self.report_value(
+                Level::Warning,
+                &at("restart_delay"),
+                format!(
+                    "restart_delay ({}ms) is larger than restart_delay_max ({}ms)",
+                    service.restart_delay, service.restart_delay_max
+                ),
+            );
+        }
+
+        // autostart
+        if service.autostart && (service.r#type != ServiceType::Service) {
+            self.report_value(
+                Level::Warning,
+                &at("autostart"),
+                "autostart is only used by services with type \"Service\"".to_string(),
+            );
+        } else if service.autostart && model::is_template(name) {
+            self.report_value(
+                Level::Warning,
+                &at("autostart"),
+                "templates can't be started automatically".to_string(),
+            );
+        }
+
+        // replicas
+        if service.replicas == 0 {
+            self.report_value(
+                Level::Error,
+                &at("replicas"),
+                "replicas must be larger than 0".to_string(),
+            );
+        }
+
+        if let Some(port) = service.base_port {
+            if port as u32 + service.replicas.max(1) - 1 > u16::MAX as u32 {
+                self.report_value(
+                    Level::Error,
+                    &at("base_port"),
+                    format!(
+                        "base_port ({port}) is too large for {} replicas",
+                        service.replicas
+                    ),
+                );
+            }
+        }
+
+        if (service.restart != RestartPolicy::Never) && (service.restart_window == 0) {
+            self.report_value(
+                Level::Error,
+                &at("restart_window"),
+                "restart_window must be larger than 0".to_string(),
+            );
+        }
+
+        if let Err(e) = unix::parse_signal(&service.stop_signal) {
+            self.report_value(Level::Error, &at("stop_signal"), e.to_string());
+        }
+
+        // schedule
+        if service.r#type == ServiceType::Timer {
+            if let Err(e) = timers::schedule(service) {
+                self.report_value(Level::Error, &at("schedule"), e.to_string());
+            }
+        } else if service.schedule.is_some() {
+            self.report_value(
+                Level::Warning,
+                &at("schedule"),
+                "schedule is only used by services with type \"Timer\"".to_string(),
+            );
+        }
+
+        // health check
+        if let Some(ref health) = service.health {
+            if health.interval == 0 {
+                self.report_value(
+                    Level::Error,
+                    &["services", name, "health", "interval"],
+                    "health check interval must be larger than 0".to_string(),
+                );
+            }
+
+            if health.threshold == 0 {
+                self.report_value(
+                    Level::Error,
+                    &["services", name, "health", "threshold"],
+                    "health check threshold must be larger than 0".to_string(),
+                );
+            }
+        }
+
+        // accounts
+        if let Err(e) = service.credentials() {
+            let key = if service.user.is_some() {
+                "user"
+            } else if service.group.is_some() {
+                "group"
+            } else {
+                "groups"
+            };
+
+            self.report_value(Level::Error, &at(key), e.to_string());
+        }
+
+        // paths that depend on the instance of a template can't be checked
+        if service.env_file.iter().any(|f| f.contains("%i")) {
+            return;
+        }
+
+        // variables in the working directory must be set (it's never expanded later)
+        let lookup = |var: &str| match config.vars.get(var) {
+            Some(v) => Some(v.to_owned()),
+            None => std::env::var(var).ok(),
+        };
+
+        if let Err(e) = vars::interpolate_strict(&service.working_directory, &lookup) {
+            self.report_value(Level::Error, &at("working_directory"), e.to_string());
+            return;
+        }
+
+        // working directory and command
+        let service = match service.resolve(&config.vars) {
+            Ok(s) => s,
+            Err(e) => {
+                self.report_value(Level::Error, &at("env_file"), e.to_string());
+                return;
+            }
+        };
+
+        // paths that depend on the instance of a template can't be checked
+        if service.working_directory.contains("%i") {
+            return;
+        }
+
+        if !Path::new(&service.working_directory).is_dir() {
+            self.report_value(
+                Level::Error,
+                &at("working_directory"),
+                format!(
+                    "working directory \"{}\" does not exist",
+                    service.working_directory
+                ),
+            );
+        }
+
+        if service.command.contains("%i") {
+            return;
+        }
+
+        if let Err(e) = check_command(&service) {
+            self.report_value(Level::Error, &at("command"), e);
+        }
+    }
+
+    /// Make sure every service in a group exists
+    fn check_groups(&mut self, config: &ServicesConfiguration) {
+        let mut groups: Vec<&String> = config.groups.keys().collect();
+        groups.sort();
+
+        for group in groups {
+            for name in &config.groups[group] {
+                if config.service(name).is_none() && !self.broken.contains(name) {
+                    self.report_value(
+                        Level::Error,
+                        &["groups", group],
+                        format!(
+                            "group \"{group}\" contains service \"{name}\", which does not exist"
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Check the server and registry settings
+    fn check_server(&mut self, config: &ServicesConfiguration) {
+        let server = &config.server;
+
+        if server.port == 0 {
+            self.report_value(
+                Level::Error,
+                &["server", "port"],
+                "server port must be larger than 0".to_string(),
+            );
+        }
+
+        if server.metrics.interval == 0 {
+            self.report_value(
+                Level::Error,
+                &["server", "metrics", "interval"],
+                "metrics interval must be larger than 0".to_string(),
+            );
+        }
+
+        if server.registry.enabled && server.key.is_empty() {
+            self.report_value(
+                Level::Warning,
+                &["server", "key"],
+                "the registry is enabled, but the server key is empty (anyone can push services)"
+                    .to_string(),
+            );
+        }
+
+        if server.registry.enabled && server.registry.name.trim().is_empty() {
+            self.report_value(
+                Level::Error,
+                &["server", "registry", "name"],
+                "registry name cannot be empty".to_string(),
+            );
+        }
+
+        for (text, url) in &server.registry.nav {
+            if text.trim().is_empty() || url.trim().is_empty() {
+                self.report_value(
+                    Level::Error,
+                    &["server", "registry", "nav"],
+                    "registry navigation buttons must have text and a URL".to_string(),
+                );
+            }
+        }
+    }
+}
+
+/// Get the error of deserializing a single value as the field `key` of `T` (values are valid if
+/// only other fields are missing)
+fn field_error<T: DeserializeOwned>(key: &str, value: &toml::Value) -> Option<toml::de::Error> {
+    let mut single = toml::Table::new();
+    single.insert(key.to_owned(), value.clone());
+
+    match toml::Value::Table(single).try_into::<T>() {
+        Ok(_) => None,
+        Err(e) if e.message().starts_with("missing field") => None,
+        Err(e) => Some(e),
+    }
+}
+
+/// Remove the values of a table that aren't valid fields of `T`, returning the error of
+/// deserializing what's left if a field is missing (and wasn't just removed)
+fn strip_invalid<T: DeserializeOwned>(table: &mut toml::Table) -> Option<toml::de::Error> {
+    let mut removed = Vec::new();
+
+    table.retain(|key, value| match field_error::<T>(key, value) {
+        Some(_) => {
+            removed.push(format!("missing field `{key}`"));
+            false
+        }
+        None => true,
+    });
+
+    toml::Value::Table(table.clone())
+        .try_into::<T>()
+        .err()
+        .filter(|e| !removed.contains(&e.message().trim().to_string()))
+}
+
+/// Get the table at `path` of a parsed file
+fn lookup<'a>(value: &'a toml::Table, path: &[&str]) -> Option<&'a toml::Table> {
+    let mut table = value;
+
+    for key in path {
+        table = table.get(*key)?.as_table()?;
+    }
+
+    Some(table)
+}
+
+/// Collect the keys of every table that aren't accepted in it, as `(key path, key)`
+fn find_unknown_keys(
+    item: &Item,
+    path: &mut Vec<String>,
+    unknown: &mut Vec<(Vec<String>, String)>,
+) {
+    let table = match item.as_table_like() {
+        Some(t) => t,
+        None => return,
+    };
+
+    let keys = known_keys(&path.iter().map(|k| k.as_str()).collect::<Vec<&str>>());
+
+    for (key, value) in table.iter() {
+        path.push(key.to_string());
+
+        if keys.is_some_and(|keys| !keys.contains(&key)) {
+            unknown.push((path.clone(), key.to_string()));
+        } else {
+            // free-form tables (`environment`, `vars`) aren't walked
+            let child: Vec<&str> = path.iter().map(|k| k.as_str()).collect();
+
+            if known_keys(&child).is_some()
+                || matches!(
+                    child[..],
+                    ["services"] | ["overlay"] | ["overlay", _, "services"]
+                )
+            {
+                find_unknown_keys(value, path, unknown);
+            }
+        }
+
+        path.pop();
+    }
+}
+
+/// Make sure the program a service runs exists and can be executed
+fn check_command(service: &Service) -> std::result::Result<(), String> {
+    if service.shell {
+        return Ok(());
+    }
+
+    let program = match service.args {
+        Some(_) => service.command.clone(),
+        None => {
+            let env = service.environment.clone().unwrap_or_default();
+            let words = shell::split(&service.command, |var| service.variable(var, &env))
+                .map_err(|e| e.to_string())?;
+
+            match words.into_iter().next() {
+                Some(p) => p,
+                None => return Err("command is empty".to_string()),
+            }
+        }
+    };
+
+    let is_executable = |path: &Path| match std::fs::metadata(path) {
+        Ok(m) => m.is_file() && (m.permissions().mode() & 0o111 != 0),
+        Err(_) => false,
+    };
+
+    // paths are relative to the working directory
+    if program.contains('/') {
+        if is_executable(&Path::new(&service.working_directory).join(&program)) {
+            return Ok(());
+        }
+
+        return Err(format!(
+            "command \"{program}\" does not exist or is not executable"
+        ));
+    }
+
+    // search PATH
+    let path = match service.environment.as_ref().and_then(|e| e.get("PATH")) {
+        Some(p) => p.to_owned(),
+        None => std::env::var("PATH").unwrap_or_default(),
+    };
+
+    if path
+        .split(':')
+        .any(|dir| is_executable(&Path::new(dir).join(&program)))
+    {
+        return Ok(());
+    }
+
+    Err(format!("command \"{program}\" was not found in PATH"))
+}
+
+/// Check a configuration file (and the files it inherits), returning every problem found
+pub fn check(path: &str) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        files: Vec::new(),
+        diagnostics: Vec::new(),
+        origins: BTreeMap::new(),
+        invalid: false,
+        broken: HashSet::new(),
+    };
+
+    let contents = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            checker.diagnostics.push(Diagnostic {
+                level: Level::Error,
+                file: path.to_string(),
+                line: 1,
+                column: 1,
+                message: e.to_string(),
+            });
+
+            return sorted(checker.diagnostics);
+        }
+    };
+
+    let main = checker.load(path, false);
+
+    if checker.files[main].document.is_none() {
+        return sorted(checker.diagnostics);
+    }
+
+    let layers = match Layers::load(&contents, path) {
+        Ok(l) => l,
+        Err(_) => return sorted(checker.diagnostics),
+    };
+
+    // inherited files that couldn't be loaded
+    for error in &layers.errors {
+        if Path::new(&error.file).is_file() {
+            // the file exists, so it can be checked for syntax errors
+            checker.load(&error.file, true);
+            continue;
+        }
+
+        let span = checker.files[main]
+            .document
+            .as_ref()
+            .and_then(|d| d.get("inherit"))
+            .and_then(|i| i.as_array())
+            .and_then(|a| a.get(error.entry))
+            .and_then(|v| v.span());
+
+        let diagnostic = checker.files[main].diagnostic(
+            Level::Error,
+            span,
+            format!(
+                "failed to load inherited file \"{}\": {}",
+                error.file, error.message
+            ),
+        );
+
+        checker.diagnostics.push(diagnostic);
+    }
+
+    // load inherited files, warning about commands that are replaced by a later file
+    let mut commands: HashMap<String, usize> = HashMap::new();
+
+    for file in &layers.files {
+        let index = match checker.files.iter().position(|f| &f.path == file) {
+            Some(i) => i,
+            None => checker.load(file, true),
+        };
+
+        let mut names: Vec<String> = match checker.files[index]
+            .document
+            .as_ref()
+            .and_then(|d| d.get("services"))
+            .and_then(|s| s.as_table_like())
+        {
+            Some(services) => services
+                .iter()
+                .filter(|(_, s)| s.get("command").is_some())
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        names.sort();
+
+        for name in names {
+            if let Some(other) = commands.get(&name) {
+                let message = format!(
+                    "replaces the command of service \"{name}\" set in \"{}\"",
+                    checker.files[*other].path
+                );
+
+                checker.report(
+                    index,
+                    Level::Warning,
+                    &["services", &name, "command"],
+                    message,
+                );
+            }
+
+            commands.insert(name, index);
+        }
+    }
+
+    if checker.invalid {
+        return sorted(checker.diagnostics);
+    }
+
+    checker.origins = layers.origins.clone();
+
+    // services that can't be deserialized are left out of the merged configuration, so the
+    // other services can still be checked (values with the wrong type were already reported in
+    // the file they were set in)
+    let mut layers = layers;
+
+    if let Some(toml::Value::Table(services)) = layers.value.get_mut("services") {
+        let mut names: Vec<String> = services.keys().cloned().collect();
+        names.sort();
+
+        for name in names {
+            if services[&name].clone().try_into::<Service>().is_ok() {
+                continue;
+            }
+
+            if let Some(toml::Value::Table(mut service)) = services.remove(&name) {
+                if let Some(e) = strip_invalid::<Service>(&mut service) {
+                    checker.report_value(
+                        Level::Error,
+                        &["services", &name],
+                        e.message().trim().to_string(),
+                    );
+                }
+            }
+
+            checker.broken.insert(name);
+        }
+    }
+
+    // the server settings are only checked if they are valid (the defaults are used otherwise)
+    let mut server = true;
+
+    if let Some(toml::Value::Table(table)) = layers.value.get("server") {
+        if table.clone().try_into::<ServerConfiguration>().is_err() {
+            if let Some(e) = strip_invalid::<ServerConfiguration>(&mut table.clone()) {
+                checker.report_value(Level::Error, &["server"], e.message().trim().to_string());
+            }
+
+            layers.value.remove("server");
+            server = false;
+        }
+    }
+
+    if let Some(e) = strip_invalid::<ServicesConfiguration>(&mut layers.value) {
+        checker.report(main, Level::Error, &[], e.message().trim().to_string());
+    }
+
+    // check the merged configuration
+    let config = match ServicesConfiguration::from_layers(&layers) {
+        Ok(c) => c,
+        Err(e) => {
+            if !checker.diagnostics.iter().any(|d| d.level == Level::Error) {
+                checker.report(main, Level::Error, &[], e.to_string());
+            }
+
+            return sorted(checker.diagnostics);
+        }
+    };
+
+    // check services
+    let mut cycles = HashSet::new();
+
+    // templates are checked too (`names` only lists the services that can be started)
+    let mut names: Vec<&String> = config.services.keys().collect();
+    names.sort();
+
+    for name in names {
+        let service = config.services[name].clone();
+        checker.check_service(name, &service, &config, &mut cycles);
+    }
+
+    checker.check_groups(&config);
+
+    if server {
+        checker.check_server(&config);
+    }
+
+    sorted(checker.diagnostics)
+}
+
+/// Sort diagnostics by file and position
+fn sorted(mut diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    diagnostics.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+    diagnostics
+}
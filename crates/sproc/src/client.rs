@@ -0,0 +1,287 @@
+//! Sproc HTTP API client
+use hyper::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+    path::PathBuf,
+};
+
+use crate::events::Event;
+use crate::logs::LogStream;
+use crate::model::{ServerConfiguration, ServiceInfo};
+use crate::server::{APIReturn, BasicServiceRequestBody, InstallRequestBody, LogsRequestBody};
+use crate::socket;
+
+/// A remote server that can be controlled with `sproc --host {name}`
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Remote {
+    /// The base URL of the server (`http://` is assumed if no scheme is given)
+    pub url: String,
+    /// Auth key
+    pub key: String,
+}
+
+/// `remotes.toml` file (`~/.config/xsu-apps/sproc/remotes.toml`)
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RemotesConfiguration {
+    /// Remote servers by name
+    #[serde(default)]
+    pub hosts: HashMap<String, Remote>,
+}
+
+impl RemotesConfiguration {
+    /// Pull remotes file
+    pub fn get_config() -> Result<Self> {
+        let home = std::env::var("HOME").expect("failed to read $HOME");
+
+        let contents =
+            match std::fs::read_to_string(format!("{home}/.config/xsu-apps/sproc/remotes.toml")) {
+                Ok(c) => c,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+                Err(e) => return Err(e),
+            };
+
+        match toml::from_str(&contents) {
+            Ok(c) => Ok(c),
+            Err(e) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Failed to read remotes file: {e}"),
+            )),
+        }
+    }
+}
+
+/// A client for the sproc API of a server (`/api/sproc`)
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// The base URL of the server
+    pub url: String,
+    /// Auth key
+    pub key: String,
+    /// The Unix socket of the server, preferred over HTTP when it can be connected to
+    pub socket: Option<PathBuf>,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Create a new [`Client`]
+    pub fn new(url: String, key: String) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            key,
+            socket: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Create a [`Client`] for the server running on this machine (using its Unix socket if it
+    /// exists)
+    pub fn local(config: &ServerConfiguration) -> Self {
+        let mut client = Self::new(
+            format!("http://localhost:{}", config.port),
+            config.key.clone(),
+        );
+
+        let socket = config.socket_path();
+
+        if socket.exists() {
+            client.socket = Some(socket);
+        }
+
+        client
+    }
+
+    /// Create a [`Client`] for a remote server from its profile in `remotes.toml`
+    pub fn remote(name: &str) -> Result<Self> {
+        let remotes = RemotesConfiguration::get_config()?;
+
+        match remotes.hosts.get(name) {
+            Some(r) if r.url.contains("://") => Ok(Self::new(r.url.clone(), r.key.clone())),
+            Some(r) => Ok(Self::new(format!("http://{}", r.url), r.key.clone())),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Remote does not exist. ({name})"),
+            )),
+        }
+    }
+
+    /// Check if the server is running
+    pub async fn ping(&self) -> bool {
+        if let Some(ref socket) = self.socket {
+            if let Ok((status, _)) =
+                socket::request(socket, Method::GET, "/api/sproc/ping", Vec::new()).await
+            {
+                return status.is_success();
+            }
+        }
+
+        match self
+            .http
+            .get(format!("{}/api/sproc/ping", self.url))
+            .send()
+            .await
+        {
+            Ok(r) => r.status().is_success(),
+            Err(_) => false,
+        }
+    }
+
+    /// Send a request to an API endpoint
+    async fn post<B: Serialize, T: DeserializeOwned + ToString>(
+        &self,
+        endpoint: &str,
+        body: &B,
+    ) -> Result<T> {
+        if let Some(ref socket) = self.socket {
+            let body = match serde_json::to_vec(body) {
+                Ok(b) => b,
+                Err(e) => return Err(Error::new(ErrorKind::InvalidInput, e.to_string())),
+            };
+
+            let uri = format!("/api/sproc/{endpoint}");
+
+            match socket::request(socket, Method::POST, &uri, body).await {
+                Ok((_, res)) => match serde_json::from_slice(&res) {
+                    Ok(r) => return Self::data(r),
+                    Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+                },
+                // fall back to http
+                Err(e) if e.kind() == ErrorKind::NotConnected => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let res: APIReturn<T> = match self
+            .http
+            .post(format!("{}/api/sproc/{endpoint}", self.url))
+            .json(body)
+            .send()
+            .await
+        {
+            Ok(r) => match r.json().await {
+                Ok(r) => r,
+                Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+            },
+            Err(e) => return Err(Error::new(ErrorKind::NotConnected, e.to_string())),
+        };
+
+        Self::data(res)
+    }
+
+    /// Get the data of an API response, or an error if the request failed
+    fn data<T: ToString>(res: APIReturn<T>) -> Result<T> {
+        if !res.ok {
+            return Err(Error::other(format!("remote: {}", res.data.to_string())));
+        }
+
+        Ok(res.data)
+    }
+
+    /// Start and observe a service
+    pub async fn start(&self, service: &str) -> Result<String> {
+        self.post("start", &self.service_body(service)).await
+    }
+
+    /// Kill a service
+    pub async fn kill(&self, service: &str) -> Result<String> {
+        self.post("kill", &self.service_body(service)).await
+    }
+
+    /// Get service info
+    pub async fn info(&self, service: &str) -> Result<ServiceInfo> {
+        let info: String = self.post("info", &self.service_body(service)).await?;
+
+        match toml::from_str(&info) {
+            Ok(i) => Ok(i),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    /// Get the last `lines` lines of a service log
+    pub async fn logs(&self, service: &str, stream: LogStream, lines: usize) -> Result<String> {
+        self.post(
+            "logs",
+            &LogsRequestBody {
+                service: service.to_string(),
+                key: self.key.clone(),
+                lines,
+                stderr: stream == LogStream::Stderr,
+                lifecycle: stream == LogStream::Lifecycle,
+            },
+        )
+        .await
+    }
+
+    /// Install a service from a registry (the server installs it in the background)
+    pub async fn install(&self, registry: &str, service: &str) -> Result<String> {
+        self.post(
+            "install",
+            &InstallRequestBody {
+                registry: registry.to_string(),
+                service: service.to_string(),
+                key: self.key.clone(),
+            },
+        )
+        .await
+    }
+
+    /// Uninstall a service
+    pub async fn uninstall(&self, service: &str) -> Result<String> {
+        self.post("uninstall", &self.service_body(service)).await
+    }
+
+    /// Read the server's event stream, calling `f` for every event
+    ///
+    /// The recent events are read first. Returns once the stream ends (which it only does when
+    /// not following new events, or if the server stops).
+    pub async fn events<F: FnMut(Event)>(&self, follow: bool, mut f: F) -> Result<()> {
+        let mut res = match self
+            .http
+            .get(format!("{}/api/sproc/events", self.url))
+            .query(&[("key", self.key.as_str()), ("follow", &follow.to_string())])
+            .send()
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(Error::new(ErrorKind::NotConnected, e.to_string())),
+        };
+
+        if !res.status().is_success() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("remote: {}", res.status()),
+            ));
+        }
+
+        // events are sent as `data: {json}` lines
+        let mut buffer = String::new();
+
+        loop {
+            let chunk = match res.chunk().await {
+                Ok(Some(c)) => c,
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(Error::new(ErrorKind::ConnectionAborted, e.to_string())),
+            };
+
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            while let Some(i) = buffer.find('\n') {
+                let line: String = buffer.drain(..=i).collect();
+
+                if let Some(data) = line.trim_end().strip_prefix("data:") {
+                    if let Ok(event) = serde_json::from_str(data.trim()) {
+                        f(event);
+                    }
+                }
+            }
+        }
+    }
+
+    fn service_body(&self, service: &str) -> BasicServiceRequestBody {
+        BasicServiceRequestBody {
+            service: service.to_string(),
+            key: self.key.clone(),
+        }
+    }
+}
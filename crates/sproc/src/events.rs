@@ -0,0 +1,174 @@
+//! Sproc service events (published by the server, streamed at `/events`)
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    sync::{Mutex, OnceLock},
+};
+use tokio::sync::broadcast;
+
+/// The number of events kept for subscribers that want to see what happened before they
+/// subscribed
+const RECENT_EVENTS: usize = 100;
+
+/// Something that happened to a service
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum EventKind {
+    /// The service process was started
+    Started { pid: u32 },
+    /// The service process exited (with an exit code, or because of a signal)
+    Exited {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    /// The service is being restarted after `delay` milliseconds
+    Restarting { restarts: u32, delay: u64 },
+    /// The health check of the service passed after failing (or the other way around)
+    HealthChanged { healthy: bool },
+    /// The service was installed from a registry
+    Installed,
+    /// The service was uninstalled
+    Uninstalled,
+}
+
+impl EventKind {
+    /// Create an [`EventKind::Exited`] from the exit status of a process
+    pub fn exited(status: &ExitStatus) -> Self {
+        Self::Exited {
+            code: status.code(),
+            signal: status.signal(),
+        }
+    }
+}
+
+/// An [`EventKind`] that happened to a service at a point in time
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Event {
+    /// When the event happened
+    pub time: String,
+    /// The name of the service
+    pub service: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl Event {
+    /// Describe the event in a single line (`sproc events`)
+    pub fn line(&self) -> String {
+        let details = match &self.kind {
+            EventKind::Started { pid } => format!("started (pid {pid})"),
+            EventKind::Exited {
+                code: Some(code), ..
+            } => format!("exited (code {code})"),
+            EventKind::Exited {
+                signal: Some(signal),
+                ..
+            } => format!("exited (signal {signal})"),
+            EventKind::Exited { .. } => "exited".to_string(),
+            EventKind::Restarting { restarts, delay } => {
+                format!("restarting (after {delay}ms, restart {restarts})")
+            }
+            EventKind::HealthChanged { healthy: true } => "healthy".to_string(),
+            EventKind::HealthChanged { healthy: false } => "unhealthy".to_string(),
+            EventKind::Installed => "installed".to_string(),
+            EventKind::Uninstalled => "uninstalled".to_string(),
+        };
+
+        format!("{} {} {details}", self.time, self.service)
+    }
+}
+
+/// The event bus
+struct Bus {
+    sender: broadcast::Sender<Event>,
+    recent: Mutex<VecDeque<Event>>,
+}
+
+static BUS: OnceLock<Bus> = OnceLock::new();
+
+fn bus() -> &'static Bus {
+    BUS.get_or_init(|| Bus {
+        sender: broadcast::channel(256).0,
+        recent: Mutex::new(VecDeque::new()),
+    })
+}
+
+/// Publish an event to every subscriber
+///
+/// Events are only seen by subscribers in the same process (the server).
+pub fn publish(service: &str, kind: EventKind) {
+    let event = Event {
+        time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        service: service.to_string(),
+        kind,
+    };
+
+    let bus = bus();
+
+    let mut recent = match bus.recent.lock() {
+        Ok(r) => r,
+        Err(e) => e.into_inner(),
+    };
+
+    recent.push_back(event.clone());
+
+    while recent.len() > RECENT_EVENTS {
+        recent.pop_front();
+    }
+
+    // there being no subscribers isn't an error
+    let _ = bus.sender.send(event);
+}
+
+/// A subscriber of the event bus
+pub struct Subscription {
+    recent: VecDeque<Event>,
+    receiver: broadcast::Receiver<Event>,
+    follow: bool,
+}
+
+impl Subscription {
+    /// Get the next event, starting with the events published before subscribing
+    ///
+    /// Returns `None` once the recent events were returned when not following new events.
+    pub async fn next(&mut self) -> Option<Event> {
+        if let Some(event) = self.recent.pop_front() {
+            return Some(event);
+        }
+
+        if !self.follow {
+            return None;
+        }
+
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                // events missed by slow subscribers are skipped
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Subscribe to events, receiving the recent events first (and every new event if `follow`
+/// is set)
+pub fn subscribe(follow: bool) -> Subscription {
+    let bus = bus();
+
+    // the lock is held so no event is published between reading the recent events and
+    // subscribing
+    let recent = match bus.recent.lock() {
+        Ok(r) => r,
+        Err(e) => e.into_inner(),
+    };
+
+    Subscription {
+        recent: recent.clone(),
+        receiver: bus.sender.subscribe(),
+        follow,
+    }
+}
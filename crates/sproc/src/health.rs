@@ -0,0 +1,196 @@
+//! Sproc service health checks
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::events::{self, EventKind};
+use crate::model::{Service, ServiceState, ServicesConfiguration};
+use crate::state::StateStore;
+
+/// [`HealthCheck`] probe type
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum HealthCheckType {
+    /// An HTTP GET request to `target` that must return a 2xx status
+    Http,
+    /// A TCP connection to `target` (`host:port`) that must succeed
+    Tcp,
+    /// A command (`target`, run with `sh -c`) that must exit with code 0
+    Command,
+}
+
+/// Health probe configuration for a [`Service`]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HealthCheck {
+    /// The type of probe
+    pub r#type: HealthCheckType,
+    /// What is probed (URL, address or command depending on `type`)
+    pub target: String,
+    /// The time (in seconds) between probes
+    #[serde(default = "interval_default")]
+    pub interval: u64,
+    /// The time (in seconds) a probe can take before it counts as failed
+    #[serde(default = "timeout_default")]
+    pub timeout: u64,
+    /// The number of failed probes in a row needed to mark the service as unhealthy
+    #[serde(default = "threshold_default")]
+    pub threshold: u32,
+}
+
+fn interval_default() -> u64 {
+    10
+}
+
+fn timeout_default() -> u64 {
+    5
+}
+
+fn threshold_default() -> u32 {
+    3
+}
+
+impl HealthCheck {
+    /// Run the probe once, returns `true` if the service is healthy
+    pub async fn probe(&self, service: &Service) -> bool {
+        let timeout = Duration::from_secs(self.timeout);
+
+        match self.r#type {
+            HealthCheckType::Http => {
+                let client = match reqwest::Client::builder().timeout(timeout).build() {
+                    Ok(c) => c,
+                    Err(_) => return false,
+                };
+
+                match client.get(&self.target).send().await {
+                    Ok(r) => r.status().is_success(),
+                    Err(_) => false,
+                }
+            }
+            HealthCheckType::Tcp => matches!(
+                tokio::time::timeout(timeout, tokio::net::TcpStream::connect(&self.target)).await,
+                Ok(Ok(_))
+            ),
+            HealthCheckType::Command => {
+                let mut cmd = tokio::process::Command::new("sh");
+                cmd.arg("-c").arg(&self.target);
+                cmd.current_dir(&service.working_directory);
+                cmd.kill_on_drop(true);
+
+                if let Some(env) = service.environment.clone() {
+                    cmd.envs(env);
+                }
+
+                // run as the same user as the service
+                match service.credentials() {
+                    Ok(Some(credentials)) => unsafe {
+                        cmd.pre_exec(move || credentials.apply());
+                    },
+                    Ok(None) => (),
+                    Err(_) => return false,
+                }
+
+                match tokio::time::timeout(timeout, cmd.status()).await {
+                    Ok(Ok(status)) => status.success(),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// Set the state of a service, if its process is still the one with the given PID
+fn update_state(name: &String, pid: u32, state: ServiceState) -> bool {
+    StateStore::update(|states| match states.get_mut(name) {
+        Some(s) => {
+            if (s.pid != pid) || !s.state.is_active() || (s.state == ServiceState::Stopping) {
+                return false;
+            }
+
+            s.state = state;
+            true
+        }
+        None => false,
+    })
+    .unwrap_or(false)
+}
+
+/// Probe the health of a service process until it exits, updating the service state
+///
+/// Unhealthy services are killed so they can be restarted by their restart policy.
+pub async fn monitor(name: String, pid: u32) {
+    let mut failures: u32 = 0;
+    let mut healthy: Option<bool> = None;
+
+    loop {
+        // pull config
+        let config = ServicesConfiguration::get_config();
+
+        let service = match config.service(&name) {
+            Some(s) => match s.resolve(&config.vars) {
+                Ok(s) => s,
+                Err(_) => return,
+            },
+            None => return,
+        };
+
+        let check = match service.health {
+            Some(ref c) => c,
+            None => return,
+        };
+
+        tokio::time::sleep(Duration::from_secs(check.interval)).await;
+
+        // make sure this process is still running
+        match StateStore::get(&name) {
+            Some(s) => {
+                if (s.pid != pid) || !s.state.is_active() || (s.state == ServiceState::Stopping) {
+                    return;
+                }
+            }
+            None => return,
+        }
+
+        // probe
+        if check.probe(&service).await {
+            failures = 0;
+
+            if healthy != Some(true) {
+                println!("info: service \"{name}\" is healthy");
+                healthy = Some(true);
+                events::publish(&name, EventKind::HealthChanged { healthy: true });
+
+                if !update_state(&name, pid, ServiceState::Healthy) {
+                    return;
+                }
+            }
+
+            continue;
+        }
+
+        failures += 1;
+
+        if (failures < check.threshold) || (healthy == Some(false)) {
+            continue;
+        }
+
+        // mark as unhealthy
+        println!("warn: service \"{name}\" is unhealthy ({failures} failed probes)");
+        healthy = Some(false);
+        events::publish(&name, EventKind::HealthChanged { healthy: false });
+
+        if !update_state(&name, pid, ServiceState::Unhealthy) {
+            return;
+        }
+
+        if service.restart.should_restart(false) {
+            // stop the process so it is restarted
+            let service = service.clone();
+
+            if let Ok(Err(e)) =
+                tokio::task::spawn_blocking(move || Service::stop_process(pid, &service)).await
+            {
+                println!("warn: failed to stop unhealthy service \"{name}\": {e}");
+            }
+
+            return;
+        }
+    }
+}
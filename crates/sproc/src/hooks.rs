@@ -0,0 +1,136 @@
+//! Sproc service lifecycle hooks (`pre_start`, `post_start`, `pre_stop`, `post_stop`)
+use std::{
+    io::{Error, ErrorKind, Result},
+    os::unix::process::CommandExt,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use crate::logs::{LogStream, ServiceLogs};
+use crate::model::Service;
+use crate::unix;
+
+/// A point in the lifecycle of a [`Service`] that commands can be run at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    /// Before the service process is started (the service isn't started if a command fails)
+    PreStart,
+    /// After the service process is started
+    PostStart,
+    /// Before the service process is stopped with `sproc kill`
+    PreStop,
+    /// After the service process stopped
+    PostStop,
+}
+
+impl Hook {
+    /// The name of the hook (and the field its commands are set in)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreStart => "pre_start",
+            Hook::PostStart => "post_start",
+            Hook::PreStop => "pre_stop",
+            Hook::PostStop => "post_stop",
+        }
+    }
+
+    /// Get the commands a service runs for this hook
+    pub fn commands<'a>(&self, service: &'a Service) -> &'a Vec<String> {
+        match self {
+            Hook::PreStart => &service.pre_start,
+            Hook::PostStart => &service.post_start,
+            Hook::PreStop => &service.pre_stop,
+            Hook::PostStop => &service.post_stop,
+        }
+    }
+}
+
+/// Run every command of a service's hook in order, stopping at the first command that fails
+///
+/// Commands are run with `sh -c`, as the service's user and with its environment (and the
+/// `vars` they use) and working directory. Their output is written to the service's logs.
+/// Commands that run for longer than `hook_timeout` are killed (and fail).
+pub fn run(name: &str, service: &Service, hook: Hook) -> Result<()> {
+    let commands = hook.commands(service);
+
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    let credentials = service.credentials()?;
+    let env = service.process_environment(&credentials);
+    let logs = ServiceLogs::new(name);
+    let timeout = Duration::from_secs(service.hook_timeout);
+
+    for command in commands {
+        println!(
+            "info: running {} hook of \"{name}\": {command}",
+            hook.name()
+        );
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd.envs(&env);
+        cmd.envs(service.shell_vars(command, &env));
+        cmd.env("SPROC_SERVICE", name);
+        cmd.env("SPROC_HOOK", hook.name());
+        cmd.current_dir(&service.working_directory);
+
+        // hooks are run in their own process group, so everything they start is killed when
+        // they time out
+        cmd.process_group(0);
+        cmd.stdout(logs.stdio(LogStream::Stdout, &service.logs)?);
+        cmd.stderr(logs.stdio(LogStream::Stderr, &service.logs)?);
+
+        if let Some(ref c) = credentials {
+            let c = c.clone();
+
+            unsafe {
+                cmd.pre_exec(move || c.apply());
+            }
+        }
+
+        let mut child = cmd.spawn()?;
+        let deadline = Instant::now() + timeout;
+
+        // wait for command to finish
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            if Instant::now() >= deadline {
+                unix::signal_group(child.id(), libc::SIGKILL)?;
+                child.wait()?;
+
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "{} hook \"{command}\" timed out after {}s. ({name})",
+                        hook.name(),
+                        service.hook_timeout
+                    ),
+                ));
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        };
+
+        if !status.success() {
+            return Err(Error::other(format!(
+                "{} hook \"{command}\" failed with {status}. ({name})",
+                hook.name()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a hook whose failure doesn't stop the service from starting or stopping, printing a
+/// warning if it fails
+pub fn run_or_warn(name: &str, service: &Service, hook: Hook) {
+    if let Err(e) = run(name, service, hook) {
+        println!("warn: {e}");
+    }
+}
@@ -0,0 +1,314 @@
+//! Sproc layered configuration (inherited files and per-host overlays)
+//!
+//! A configuration is built from layers, which are deep-merged in order: the main file, every
+//! file it inherits (in the order they are listed), and then the `[overlay.<hostname>]` section
+//! matching this machine. Tables are merged key by key, every other value (including arrays)
+//! replaces the value of the layers before it.
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+use toml::{Table, Value};
+
+use crate::unix;
+
+/// Where a configuration value was set
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// The file the value was set in
+    pub file: String,
+    /// The host the value was set for (`[overlay.<host>]`)
+    pub overlay: Option<String>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.overlay {
+            Some(ref host) => write!(f, "{} [overlay.{host}]", self.file),
+            None => f.write_str(&self.file),
+        }
+    }
+}
+
+/// A configuration value and where it was set (`sproc pinned --explain`)
+#[derive(Serialize, Debug, Clone)]
+pub struct Explained {
+    /// The dotted key path of the value
+    pub key: String,
+    /// The value (as TOML)
+    pub value: String,
+    #[serde(flatten)]
+    pub origin: Origin,
+}
+
+/// A problem with a file listed in `inherit`
+#[derive(Debug, Clone)]
+pub struct InheritError {
+    /// The index of the `inherit` entry
+    pub entry: usize,
+    /// The file that couldn't be loaded
+    pub file: String,
+    pub message: String,
+}
+
+/// A configuration merged from all of its layers
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+    /// The merged configuration
+    pub value: Table,
+    /// Where every value (that isn't a table) of `value` was set, by key path
+    pub origins: BTreeMap<Vec<String>, Origin>,
+    /// Every file that was merged, in order
+    pub files: Vec<String>,
+    /// Inherited files that couldn't be loaded (and were skipped)
+    pub errors: Vec<InheritError>,
+}
+
+impl Layers {
+    /// Load a configuration and every layer on top of it
+    ///
+    /// `file` is the path of the main configuration file (relative `inherit` paths are relative
+    /// to its directory). The `source` field is used instead if `file` is empty.
+    pub fn load(contents: &str, file: &str) -> Result<Self> {
+        let root: Table = match toml::from_str(contents) {
+            Ok(t) => t,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        };
+
+        let file = match (file, root.get("source").and_then(|s| s.as_str())) {
+            ("", Some(source)) if !source.is_empty() => source.to_string(),
+            ("", _) => "services.toml".to_string(),
+            (file, _) => file.to_string(),
+        };
+
+        let dir = match Path::new(&file).parent() {
+            Some(p) => p.to_path_buf(),
+            None => Path::new(".").to_path_buf(),
+        };
+
+        let mut layers = Self::default();
+
+        let inherit: Vec<String> = match root.get("inherit").and_then(|i| i.as_array()) {
+            Some(a) => a
+                .iter()
+                .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        layers.merge(root, &file);
+
+        // merge inherited files
+        for (entry, pattern) in inherit.iter().enumerate() {
+            let paths = match expand(pattern, &dir) {
+                Ok(p) => p,
+                Err(e) => {
+                    layers.errors.push(InheritError {
+                        entry,
+                        file: pattern.to_owned(),
+                        message: e.to_string(),
+                    });
+
+                    continue;
+                }
+            };
+
+            for path in paths {
+                let mut inherited = match std::fs::read_to_string(&path) {
+                    Ok(c) => match toml::from_str::<Table>(&c) {
+                        Ok(t) => t,
+                        Err(e) => {
+                            layers.errors.push(InheritError {
+                                entry,
+                                file: path,
+                                message: e.to_string(),
+                            });
+
+                            continue;
+                        }
+                    },
+                    Err(e) => {
+                        layers.errors.push(InheritError {
+                            entry,
+                            file: path,
+                            message: e.to_string(),
+                        });
+
+                        continue;
+                    }
+                };
+
+                // inherited files can't inherit other files (or replace the source)
+                inherited.remove("inherit");
+                inherited.remove("source");
+
+                layers.merge(inherited, &path);
+            }
+        }
+
+        // apply overlay
+        if let Some(Value::Table(mut overlays)) = layers.value.remove("overlay") {
+            let host = unix::hostname();
+
+            if let Some(Value::Table(overlay)) = overlays.remove(&host) {
+                let mut overlay_origins = BTreeMap::new();
+
+                for (path, origin) in &layers.origins {
+                    if (path.len() > 2) && (path[0] == "overlay") && (path[1] == host) {
+                        overlay_origins.insert(path[2..].to_vec(), origin.file.clone());
+                    }
+                }
+
+                layers.apply_overlay(overlay, &host, &overlay_origins);
+            }
+
+            layers.origins.retain(|path, _| path[0] != "overlay");
+        }
+
+        Ok(layers)
+    }
+
+    /// Merge a layer on top of the current value
+    fn merge(&mut self, layer: Table, file: &str) {
+        if !self.files.iter().any(|f| f == file) {
+            self.files.push(file.to_string());
+        }
+
+        let origin = Origin {
+            file: file.to_string(),
+            overlay: None,
+        };
+
+        let origins = &mut self.origins;
+
+        merge_table(&mut self.value, layer, &mut Vec::new(), &mut |path| {
+            origins.insert(path.to_vec(), origin.clone());
+        });
+    }
+
+    /// Merge a host's overlay on top of the current value, keeping the files its values came
+    /// from
+    fn apply_overlay(&mut self, overlay: Table, host: &str, files: &BTreeMap<Vec<String>, String>) {
+        let origins = &mut self.origins;
+
+        merge_table(&mut self.value, overlay, &mut Vec::new(), &mut |path| {
+            origins.insert(
+                path.to_vec(),
+                Origin {
+                    file: files.get(path).cloned().unwrap_or_default(),
+                    overlay: Some(host.to_string()),
+                },
+            );
+        });
+    }
+
+    /// Get every value (that isn't a table) of the configuration with its origin
+    pub fn explain(&self) -> Vec<Explained> {
+        let mut out = Vec::new();
+
+        for (path, origin) in &self.origins {
+            // values that were replaced by a table (or are inside a replaced table) are skipped
+            if let Some(value) = lookup(&self.value, path) {
+                if value.is_table() {
+                    continue;
+                }
+
+                out.push(Explained {
+                    key: path.join("."),
+                    value: value.to_string(),
+                    origin: origin.clone(),
+                });
+            }
+        }
+
+        out
+    }
+}
+
+/// Deep-merge `layer` into `base`, calling `set` with the path of every value that is set
+fn merge_table(
+    base: &mut Table,
+    layer: Table,
+    path: &mut Vec<String>,
+    set: &mut impl FnMut(&[String]),
+) {
+    for (key, value) in layer {
+        path.push(key.clone());
+
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge_table(existing, table, path, set);
+            }
+            (_, Value::Table(table)) => {
+                // replace with an empty table first, so every value inside is recorded
+                base.insert(key.clone(), Value::Table(Table::new()));
+
+                if let Some(Value::Table(existing)) = base.get_mut(&key) {
+                    merge_table(existing, table, path, set);
+                }
+            }
+            (_, value) => {
+                base.insert(key.clone(), value);
+                set(path);
+            }
+        }
+
+        path.pop();
+    }
+}
+
+/// Get the value at a key path
+fn lookup<'a>(table: &'a Table, path: &[String]) -> Option<&'a Value> {
+    let (first, rest) = path.split_first()?;
+    let value = table.get(first)?;
+
+    if rest.is_empty() {
+        return Some(value);
+    }
+
+    match value {
+        Value::Table(t) => lookup(t, rest),
+        _ => None,
+    }
+}
+
+/// Get the files matching an `inherit` entry (a path or a glob pattern like `services.d/*.toml`),
+/// sorted by name
+///
+/// Relative entries are relative to `dir`. Glob patterns can match no files, but paths must
+/// exist.
+pub fn expand(pattern: &str, dir: &Path) -> Result<Vec<String>> {
+    let full = if Path::new(pattern).is_absolute() {
+        pattern.to_string()
+    } else {
+        dir.join(pattern).to_string_lossy().to_string()
+    };
+
+    if !pattern.contains(['*', '?', '[']) {
+        if !Path::new(&full).exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Inherited file does not exist. ({full})"),
+            ));
+        }
+
+        return Ok(vec![full]);
+    }
+
+    let paths = match glob::glob(&full) {
+        Ok(p) => p,
+        Err(e) => return Err(Error::new(ErrorKind::InvalidInput, e.to_string())),
+    };
+
+    let mut files: Vec<String> = paths
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_file())
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+
+    files.sort();
+    Ok(files)
+}
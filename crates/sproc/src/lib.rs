@@ -2,7 +2,24 @@
 #![doc = include_str!("../README.md")]
 #![doc(issue_tracker_base_url = "https://github.com/hkauso/sproc/issues/")]
 
+pub mod check;
+pub mod client;
+pub mod events;
+pub mod health;
+pub mod hooks;
+pub mod layers;
+pub mod limits;
+pub mod logs;
+pub mod metrics;
 pub mod model;
+pub mod output;
 pub mod server;
+pub mod shell;
+pub mod socket;
+pub mod state;
+pub mod timers;
+pub mod unix;
+pub mod vars;
+pub mod watch;
 
 pub use model::Service;
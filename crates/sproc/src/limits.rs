@@ -0,0 +1,59 @@
+//! Sproc service resource limits
+use serde::{Deserialize, Serialize};
+use std::io::{Error, Result};
+
+/// Resource limits applied to a [`Service`](crate::model::Service) process (with `setrlimit`)
+///
+/// Every limit is used as both the soft and hard limit, so the service can't raise it again.
+/// Limits are inherited by every process the service starts.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// The maximum size (in bytes) of the process's virtual memory (`RLIMIT_AS`)
+    pub memory: Option<u64>,
+    /// The maximum number of open file descriptors (`RLIMIT_NOFILE`)
+    pub open_files: Option<u64>,
+    /// The maximum CPU time (in seconds) the process can use (`RLIMIT_CPU`)
+    pub cpu: Option<u64>,
+    /// The maximum number of processes the service's user can have (`RLIMIT_NPROC`)
+    pub processes: Option<u64>,
+    /// The maximum size (in bytes) of core dumps, `0` disables them (`RLIMIT_CORE`)
+    pub core: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// If no limits are set
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Apply the limits to the current process
+    ///
+    /// This is run in the forked service process before `exec`, so it must not allocate.
+    pub fn apply(&self) -> Result<()> {
+        let limits = [
+            (libc::RLIMIT_AS, self.memory),
+            (libc::RLIMIT_NOFILE, self.open_files),
+            (libc::RLIMIT_CPU, self.cpu),
+            (libc::RLIMIT_NPROC, self.processes),
+            (libc::RLIMIT_CORE, self.core),
+        ];
+
+        for (resource, value) in limits {
+            let value = match value {
+                Some(v) => v as libc::rlim_t,
+                None => continue,
+            };
+
+            let limit = libc::rlimit {
+                rlim_cur: value,
+                rlim_max: value,
+            };
+
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! Sproc service logs (`~/.config/xsu-apps/sproc/logs/:name`)
+use serde::{Deserialize, Serialize};
+use chrono::Local;
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{Read, Result, Seek, SeekFrom, Write},
+    process::Stdio,
+    time::Duration,
+};
+use xsu_util::fs;
+
+use crate::model::ServicesConfiguration;
+use crate::state::StateStore;
+
+/// Log limits for a [`Service`](crate::model::Service)
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct LogConfiguration {
+    /// The size (in bytes) a log file can grow to before it is rotated (10 MB by default)
+    #[serde(default = "max_size_default")]
+    pub max_size: u64,
+    /// The number of rotated log files kept next to the current one (5 by default)
+    #[serde(default = "max_files_default")]
+    pub max_files: usize,
+}
+
+fn max_size_default() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn max_files_default() -> usize {
+    5
+}
+
+impl Default for LogConfiguration {
+    fn default() -> Self {
+        Self {
+            max_size: max_size_default(),
+            max_files: max_files_default(),
+        }
+    }
+}
+
+/// A service log stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    /// Events recorded by sproc (starts, exits, restarts, ...)
+    Lifecycle,
+}
+
+impl LogStream {
+    /// The name of the stream's log file
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout.log",
+            LogStream::Stderr => "stderr.log",
+            LogStream::Lifecycle => "lifecycle.log",
+        }
+    }
+}
+
+/// The log files of a single service
+#[derive(Debug, Clone)]
+pub struct ServiceLogs(pub String);
+
+impl ServiceLogs {
+    /// Get the logs of the given service
+    pub fn new(name: &str) -> Self {
+        let home = env::var("HOME").expect("failed to read $HOME");
+        Self(format!("{home}/.config/xsu-apps/sproc/logs/{name}"))
+    }
+
+    /// Get the path of a stream's (current) log file
+    pub fn path(&self, stream: LogStream) -> String {
+        format!("{}/{}", self.0, stream.file_name())
+    }
+
+    /// Create the logs directory
+    fn init(&self) -> Result<()> {
+        let home = env::var("HOME").expect("failed to read $HOME");
+        fs::mkdir(format!("{home}/.config/xsu-apps/sproc/logs"))?;
+        fs::mkdir(&self.0)
+    }
+
+    /// Rotate a stream's log file if it has grown past the size limit
+    ///
+    /// The current file is copied and then truncated (instead of renamed) so a running
+    /// process can keep writing to it.
+    pub fn rotate(&self, stream: LogStream, config: &LogConfiguration) -> Result<()> {
+        let path = self.path(stream);
+
+        match std::fs::metadata(&path) {
+            Ok(m) => {
+                if m.len() < config.max_size {
+                    return Ok(());
+                }
+            }
+            Err(_) => return Ok(()),
+        }
+
+        // shift old files (oldest file is dropped)
+        if config.max_files > 0 {
+            let _ = std::fs::remove_file(format!("{path}.{}", config.max_files));
+
+            for i in (1..config.max_files).rev() {
+                let _ = std::fs::rename(format!("{path}.{i}"), format!("{path}.{}", i + 1));
+            }
+
+            std::fs::copy(&path, format!("{path}.1"))?;
+        }
+
+        // truncate current file
+        OpenOptions::new().write(true).open(&path)?.set_len(0)
+    }
+
+    /// Open a stream's log file so it can be given to a new process
+    pub fn stdio(&self, stream: LogStream, config: &LogConfiguration) -> Result<Stdio> {
+        self.init()?;
+        self.rotate(stream, config)?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(stream))?;
+
+        Ok(Stdio::from(file))
+    }
+
+    /// Record an event in the lifecycle log (printing a warning if it can't be written)
+    pub fn event(&self, message: &str) {
+        let res = self.init().and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(LogStream::Lifecycle))?
+                .write_all(
+                    format!("{} {message}\n", Local::now().format("%Y-%m-%d %H:%M:%S")).as_bytes(),
+                )
+        });
+
+        if let Err(e) = res {
+            println!("warn: failed to write lifecycle log ({}): {e}", self.0);
+        }
+    }
+
+    /// Read the last `lines` lines of a stream's log file
+    pub fn tail(&self, stream: LogStream, lines: usize) -> Result<String> {
+        let mut buffer = Vec::new();
+        File::open(self.path(stream))?.read_to_end(&mut buffer)?;
+
+        let contents = String::from_utf8_lossy(&buffer);
+        let all: Vec<&str> = contents.lines().collect();
+
+        Ok(all[all.len().saturating_sub(lines)..].join("\n"))
+    }
+
+    /// Print a stream's log file as it grows (never returns unless the file can't be read)
+    pub async fn follow(&self, stream: LogStream) -> Result<()> {
+        let path = self.path(stream);
+        let mut position = std::fs::metadata(&path)?.len();
+
+        loop {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            let len = std::fs::metadata(&path)?.len();
+
+            if len < position {
+                // file was rotated, start from the beginning
+                position = 0;
+            }
+
+            if len == position {
+                continue;
+            }
+
+            // print new data
+            let mut file = File::open(&path)?;
+            file.seek(SeekFrom::Start(position))?;
+
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
+            print!("{}", String::from_utf8_lossy(&buffer));
+
+            position += buffer.len() as u64;
+        }
+    }
+}
+
+/// Rotate the logs of all running services every minute
+pub async fn rotate_task() {
+    loop {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let config = ServicesConfiguration::get_config();
+
+        for name in StateStore::read().keys() {
+            let service = match config.service(name) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let logs = ServiceLogs::new(name);
+
+            for stream in [LogStream::Stdout, LogStream::Stderr, LogStream::Lifecycle] {
+                if let Err(e) = logs.rotate(stream, &service.logs) {
+                    println!("warn: failed to rotate logs of \"{name}\": {e}");
+                }
+            }
+        }
+    }
+}
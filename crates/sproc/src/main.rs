@@ -1,12 +1,9 @@
 //! Sproc process manager
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use server::APIReturn;
 use std::io::{Error, ErrorKind, Result};
 
-use xsu_util::{
-    fs,
-    process::{no, yes},
-};
+use xsu_util::fs;
 
 // ...
 #[derive(Parser, Debug)]
@@ -15,30 +12,150 @@ use xsu_util::{
 struct Sproc {
     #[command(subcommand)]
     command: Commands,
+    /// The format to print output in
+    #[arg(short, long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+    /// Send commands to the remote server with this name (from `remotes.toml`)
+    #[arg(long, global = true)]
+    host: Option<String>,
+}
+
+/// Services selected by name, tag or group
+#[derive(Args, Debug)]
+struct Selection {
+    names: Vec<String>,
+    /// Select every service with this tag
+    #[arg(long)]
+    tag: Vec<String>,
+    /// Select every service in this group
+    #[arg(long)]
+    group: Vec<String>,
+}
+
+impl Selection {
+    /// Get the names of the selected services
+    fn names(&self, config: &ServicesConfiguration) -> Result<Vec<String>> {
+        let names = config.select(&self.names, &self.tag, &self.group)?;
+
+        if names.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Please provide at least 1 service name, tag or group.",
+            ));
+        }
+
+        Ok(names)
+    }
+
+    /// Get the names of the selected services on a remote server (which can only be selected
+    /// by name, since the remote configuration isn't known)
+    fn remote_names(&self) -> Result<&Vec<String>> {
+        if !self.tag.is_empty() || !self.group.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Services can only be selected by name with --host.",
+            ));
+        }
+
+        if self.names.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Please provide at least 1 service name.",
+            ));
+        }
+
+        Ok(&self.names)
+    }
+
+    /// If a single service was requested by name
+    fn is_single(&self) -> bool {
+        (self.names.len() == 1) && self.tag.is_empty() && self.group.is_empty()
+    }
+
+    /// If a service is the single service selected by name (not a service selected by tag or
+    /// group, or a replica of the selected service)
+    fn is_explicit(&self, name: &String) -> bool {
+        self.is_single() && self.names.contains(name)
+    }
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Load configuration file
     Pin { path: String },
+    /// Check a configuration file (and the files it inherits) for problems
+    Check { path: String },
     /// Run a configured service
-    Run { names: Vec<String> },
+    Run {
+        #[command(flatten)]
+        select: Selection,
+    },
     /// Spawn a service as a new task (HTTP server required: `xsu-cliff`)
-    Spawn { names: Vec<String> },
+    Spawn {
+        #[command(flatten)]
+        select: Selection,
+    },
     /// Run all services
     RunAll {},
     /// Kill a running service
-    Kill { names: Vec<String> },
+    Kill {
+        #[command(flatten)]
+        select: Selection,
+    },
     /// Kill all services
     KillAll {},
+    /// Save the running services so they are started again by `resurrect` (and by the server
+    /// when it starts)
+    Save {},
+    /// Start the services saved with `save`
+    ///
+    /// Services that are still running are skipped. Services that kept running while the server
+    /// was stopped aren't supervised again when it starts (their restart policy and health checks
+    /// don't apply until they are restarted).
+    Resurrect {},
+    /// Start or stop replicas of a service until the given number of replicas is running
+    Scale { name: String, replicas: u32 },
     /// Get information about a running service
-    Info { name: String },
+    Info {
+        #[command(flatten)]
+        select: Selection,
+    },
     /// Get information about all services
     InfoAll {},
+    /// Show the state of every configured service
+    Status {},
+    /// Show recent service events (HTTP server required)
+    Events {
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// List timer services and when they run next
+    Timers {},
     /// Wait for service to stop and update its state accordingly
     Track { name: String },
+    /// View the output of a service
+    Logs {
+        name: String,
+        /// Keep printing output as it is written
+        #[arg(short, long)]
+        follow: bool,
+        /// The number of lines to print
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+        /// View stderr instead of stdout
+        #[arg(long)]
+        stderr: bool,
+        /// View the events recorded by sproc (starts, exits, restarts, ...) instead of stdout
+        #[arg(long, conflicts_with = "stderr")]
+        lifecycle: bool,
+    },
     /// View pinned config
-    Pinned {},
+    Pinned {
+        /// Show the file every value was set in (before variables are resolved)
+        #[arg(long)]
+        explain: bool,
+    },
     /// Merge services from given file into **source** configuration file (unpinned file)
     Merge { path: String },
     /// Pull services from given file into **pinned** configuration file (use `merge` for unpinned)
@@ -52,19 +169,82 @@ enum Commands {
     Uninstall { names: Vec<String> },
 }
 
+impl Commands {
+    /// If the command reads or changes service states (stale states are removed first)
+    fn uses_state(&self) -> bool {
+        matches!(
+            self,
+            Commands::Pin { .. }
+                | Commands::Run { .. }
+                | Commands::RunAll {}
+                | Commands::Kill { .. }
+                | Commands::KillAll {}
+                | Commands::Save {}
+                | Commands::Resurrect {}
+                | Commands::Scale { .. }
+                | Commands::Info { .. }
+                | Commands::InfoAll {}
+                | Commands::Status {}
+                | Commands::Timers {}
+                | Commands::Track { .. }
+                | Commands::Uninstall { .. }
+        )
+    }
+}
+
 // ...
+pub mod check;
+pub mod client;
+pub mod events;
+pub mod health;
+pub mod hooks;
+pub mod layers;
+pub mod limits;
+pub mod logs;
+pub mod metrics;
 pub mod model;
+pub mod output;
 pub mod server;
+pub mod shell;
+pub mod socket;
+pub mod state;
+pub mod timers;
+pub mod unix;
+pub mod vars;
+pub mod watch;
 
-use model::{Service, ServiceState, ServiceType, ServicesConfiguration};
+use client::Client;
+use hooks::Hook;
+use layers::Layers;
+use logs::{LogStream, ServiceLogs};
+use output::OutputFormat;
+use state::{SavedServices, StateStore};
+use model::{Service, ServiceInfo, ServiceState, ServiceStatus, ServiceType, ServicesConfiguration};
 
 // real main
-async fn sproc<'a>() -> Result<&'a str> {
-    // init
-    let args = Sproc::parse();
+async fn sproc<'a>(args: &Sproc) -> Result<&'a str> {
+    let format = args.output;
+
+    // commands are sent to a remote server with `--host`
+    if let Some(ref host) = args.host {
+        return remote(args, &Client::remote(host)?).await;
+    }
 
     // get current config
-    let mut services = ServicesConfiguration::get_config();
+    let services = ServicesConfiguration::get_config();
+
+    // clean up states of services that are no longer running
+    if args.command.uses_state() {
+        for name in StateStore::prune_stale()? {
+            output::log(
+                format,
+                &format!("warn: removed stale state of service \"{name}\""),
+            );
+        }
+    }
+
+    // services are managed by the server when it is running
+    let client = Client::local(&services.server);
 
     // ...
     match &args.command {
@@ -73,14 +253,17 @@ async fn sproc<'a>() -> Result<&'a str> {
             match fs::read(path) {
                 Ok(s) => {
                     // make sure no services are running
-                    for service in services.service_states {
-                        if service.1 .0 == ServiceState::Running {
+                    for service in StateStore::read() {
+                        if service.1.state.is_active() {
                             return Err(Error::new(ErrorKind::Other, "Cannot pin config with active service. Please run \"sproc kill-all\""));
                         }
                     }
 
                     // ...
-                    let mut config: ServicesConfiguration = toml::from_str(&s).unwrap();
+                    let mut config: ServicesConfiguration = match toml::from_str(&s) {
+                        Ok(c) => c,
+                        Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+                    };
 
                     // set source to absolute path
                     config.source = fs::canonicalize(path)?
@@ -89,6 +272,38 @@ async fn sproc<'a>() -> Result<&'a str> {
                         .unwrap()
                         .to_string();
 
+                    // apply every layer now, so problems with inherited files are found
+                    let layers = Layers::load(&s, &config.source)?;
+
+                    if let Some(e) = layers.errors.first() {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "Failed to load inherited file \"{}\": {}",
+                                e.file, e.message
+                            ),
+                        ));
+                    }
+
+                    let merged = ServicesConfiguration::from_layers(&layers)?;
+                    model::check_service_names(merged.services.keys())?;
+
+                    // make sure the accounts services are run as exist, and timers can be scheduled
+                    for (name, service) in &merged.services {
+                        if let Err(e) = service.credentials() {
+                            return Err(Error::new(e.kind(), format!("{e} (service \"{name}\")")));
+                        }
+
+                        if service.r#type == ServiceType::Timer {
+                            if let Err(e) = timers::schedule(service) {
+                                return Err(Error::new(
+                                    e.kind(),
+                                    format!("{e} (service \"{name}\")"),
+                                ));
+                            }
+                        }
+                    }
+
                     // return
                     ServicesConfiguration::update_config(config)?;
                     Ok("Services loaded.")
@@ -96,85 +311,123 @@ async fn sproc<'a>() -> Result<&'a str> {
                 Err(e) => Err(e),
             }
         }
-        // run
-        Commands::Run { names } => {
-            if names.len() == 0 {
+        // check
+        Commands::Check { path } => {
+            let diagnostics = check::check(path);
+
+            if format.is_structured() {
+                output::print(format, "diagnostics", &diagnostics)?;
+            } else {
+                for diagnostic in &diagnostics {
+                    println!("{diagnostic}");
+                }
+            }
+
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.level == check::Level::Error)
+                .count();
+
+            if errors > 0 {
                 return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Please provide at least 1 service name.",
+                    ErrorKind::InvalidData,
+                    format!("Found {errors} error(s) in configuration."),
                 ));
             }
 
-            for name in names {
-                match services.services.get(name) {
-                    Some(_) => {
-                        let mut process = Service::run(name.to_string(), services.clone())?;
-
-                        // if this is an application, wait for it to close and then continue
-                        if process.0.r#type == ServiceType::Application {
-                            process.1.wait()?;
-                            continue; // we must continue so we don't try to add the service pid
-                        }
+            Ok("Configuration is valid.")
+        }
+        // run
+        Commands::Run { select } => {
+            let names = &select.names(&services)?;
+
+            // services selected by tag or group (or replicas) that are already running are skipped
+            let states = StateStore::read();
+            let skip = |name: &String| {
+                !select.is_explicit(name) && states.get(name).is_some_and(|s| s.state.is_active())
+            };
+
+            // let the server start (and supervise) services if it is running
+            if client.ping().await {
+                for name in names {
+                    if skip(name) {
+                        continue;
+                    }
+
+                    if services.service(name).map(|s| s.r#type) == Some(ServiceType::Application) {
+                        Service::run(name.to_string(), services.clone())?.1.wait()?;
+                        continue;
+                    }
+
+                    output::log(format, &format!("info: {}", client.start(name).await?));
+                }
+
+                return Ok("Started all requested services.");
+            }
 
-                        // ...
-                        services
-                            .service_states
-                            .insert(name.to_string(), (ServiceState::Running, process.1.id()));
+            // dependencies are started first
+            for name in services.start_order(names)? {
+                if let Some(s) = states.get(&name) {
+                    // dependencies that are already running don't need to be started again
+                    if s.state.is_active() && !names.contains(&name) {
+                        continue;
                     }
-                    None => return Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
                 }
+
+                if skip(&name) {
+                    continue;
+                }
+
+                let mut process = Service::run(name.to_string(), services.clone())?;
+
+                // if this is an application, wait for it to close and then continue
+                if process.0.r#type == ServiceType::Application {
+                    process.1.wait()?;
+                    continue; // we must continue so we don't try to add the service pid
+                }
+
+                // ...
+                StateStore::set(&name, ServiceStatus::running(process.1.id()))?;
             }
 
-            ServicesConfiguration::update_config(services)?;
             Ok("Started all requested services.")
         }
         // spawn
-        Commands::Spawn { names } => {
-            if names.len() == 0 {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Please provide at least 1 service name.",
-                ));
-            }
-
+        Commands::Spawn { select } => {
             // post request
-            let client = reqwest::Client::new();
-
-            for name in names {
-                match services.services.get(name) {
-                    Some(_) => {
-                        match client
-                            .post(format!(
-                                "http://localhost:{}/api/sproc/start",
-                                services.server.port
-                            ))
-                            .body(format!(
-                                "{{ \"service\":\"{}\",\"key\":\"{}\" }}",
-                                name, services.server.key
-                            ))
-                            .header("Content-Type", "application/json")
-                            .send()
-                            .await
-                        {
-                            Ok(r) => {
-                                let res = r.text().await.expect("Failed to read body");
-                                println!("info: body: {}", res);
-                            }
-                            Err(e) => {
-                                return Err(Error::new(ErrorKind::NotConnected, e.to_string()))
-                            }
-                        }
-                    }
-                    None => return Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
-                }
+            for name in select.names(&services)? {
+                output::log(format, &format!("info: {}", client.start(&name).await?));
             }
 
             Ok("Sent all requested requests.")
         }
         // runall
         Commands::RunAll {} => {
-            for service in &services.services {
-                let mut process = Service::run(service.0.to_string(), services.clone())?;
+            let server = client.ping().await;
+
+            let names = services.with_replicas(&services.names(), &StateStore::read());
+
+            for name in services.start_order(&names)? {
+                let r#type = services
+                    .service(&name)
+                    .map(|s| s.r#type)
+                    .unwrap_or_default();
+
+                // timers are run on their schedule
+                if r#type == ServiceType::Timer {
+                    continue;
+                }
+
+                // let the server start services if it is running
+                if server && (r#type != ServiceType::Application) {
+                    if let Err(e) = client.start(&name).await {
+                        output::log(format, &format!("warn: {e}"));
+                    }
+
+                    continue;
+                }
+
+                let mut process = Service::run(name.to_string(), services.clone())?;
 
                 // if this is an application, immediately exit
                 if process.0.r#type == ServiceType::Application {
@@ -183,106 +436,357 @@ async fn sproc<'a>() -> Result<&'a str> {
                 }
 
                 // ...
-                services.service_states.insert(
-                    service.0.to_string(),
-                    (ServiceState::Running, process.1.id()),
-                );
+                StateStore::set(&name, ServiceStatus::running(process.1.id()))?;
             }
 
-            ServicesConfiguration::update_config(services)?;
             Ok("Started all services.")
         }
         // kill
-        Commands::Kill { names } => {
-            if names.len() == 0 {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Please provide at least 1 service name.",
-                ));
-            }
+        Commands::Kill { select } => {
+            let names = select.names(&services)?;
 
-            for name in names {
-                match services.services.get(name) {
-                    Some(_) => {
-                        Service::kill(name.to_string(), services.clone())?;
-                        services.service_states.remove(name);
-                    }
-                    None => return Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
+            // dependents are stopped before their dependencies
+            let server = client.ping().await;
+            let states = StateStore::read();
+
+            for name in services.stop_order(&names) {
+                // services selected by tag or group (or replicas) that aren't running are skipped
+                if !select.is_explicit(&name)
+                    && !states.get(&name).is_some_and(|s| s.state.is_active())
+                {
+                    continue;
+                }
+
+                if server {
+                    client.kill(&name).await?;
+                } else {
+                    Service::kill(name.to_string(), services.clone())?;
                 }
             }
 
             // return
-            ServicesConfiguration::update_config(services.clone())?;
             Ok("Stopped all given services.")
         }
         // kill-all
         Commands::KillAll {} => {
-            for service in &services.services {
-                if let Err(e) = Service::kill(service.0.to_string(), services.clone()) {
-                    println!("warn: {}", e.to_string());
+            let server = client.ping().await;
+            let names = services.names_with_instances(&StateStore::read());
+
+            for name in services.stop_order(&names) {
+                let res = if server {
+                    client.kill(&name).await.map(|_| ())
+                } else {
+                    Service::kill(name.to_string(), services.clone())
+                };
+
+                if let Err(e) = res {
+                    output::log(format, &format!("warn: {e}"));
                 }
 
                 // if we couldn't get the pid then the service probably ran and exited already
-                services.service_states.remove(service.0);
+                StateStore::remove(&name)?;
             }
 
             // return
-            ServicesConfiguration::update_config(services)?;
             Ok("Stopped all services.")
         }
+        // save
+        Commands::Save {} => {
+            let saved = SavedServices::save()?;
+
+            if format.is_structured() {
+                output::print(format, "services", &saved)?;
+            }
+
+            Ok("Saved running services.")
+        }
+        // resurrect
+        Commands::Resurrect {} => {
+            let server = client.ping().await;
+            let states = StateStore::read();
+            let mut names = Vec::new();
+
+            for name in SavedServices::read().services {
+                if services.service(&name).is_none() {
+                    output::log(
+                        format,
+                        &format!("warn: saved service no longer exists: {name}"),
+                    );
+                    continue;
+                }
+
+                names.push(name);
+            }
+
+            // dependencies are started first
+            for name in services.start_order(&names)? {
+                if states.get(&name).is_some_and(|s| s.state.is_active()) {
+                    continue;
+                }
+
+                // applications are run by people, and timers on their schedule
+                if services.service(&name).map(|s| s.r#type) != Some(ServiceType::Service) {
+                    continue;
+                }
+
+                // let the server start (and supervise) services if it is running
+                if server {
+                    output::log(format, &format!("info: {}", client.start(&name).await?));
+                    continue;
+                }
+
+                let process = Service::run(name.to_string(), services.clone())?;
+                StateStore::set(&name, ServiceStatus::running(process.1.id()))?;
+            }
+
+            Ok("Resurrected saved services.")
+        }
+        // scale
+        Commands::Scale { name, replicas } => {
+            match services.service(name) {
+                Some(s) if s.r#type != ServiceType::Service => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Only services can be scaled. ({name})"),
+                    ))
+                }
+                Some(_) if model::replica_of(name).1 > 0 => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Replicas can't be scaled, scale their service instead. ({name})"),
+                    ))
+                }
+                Some(_) => (),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Service does not exist. ({name})"),
+                    ))
+                }
+            }
+
+            let server = client.ping().await;
+            let states = StateStore::read();
+            let is_active = |n: &String| states.get(n).is_some_and(|s| s.state.is_active());
+
+            let wanted = model::replica_names(name, *replicas);
+            let mut running: Vec<String> = services
+                .with_replicas(&[name.to_owned()], &states)
+                .into_iter()
+                .filter(is_active)
+                .collect();
+
+            running.sort_by_key(|r| model::replica_of(r).1);
+
+            // stop replicas that are no longer needed (newest first)
+            for replica in running.iter().rev().filter(|r| !wanted.contains(r)) {
+                if server {
+                    client.kill(replica).await?;
+                } else {
+                    Service::kill(replica.to_owned(), services.clone())?;
+                }
+            }
+
+            // start missing replicas
+            for replica in wanted.iter().filter(|r| !running.contains(r)) {
+                if server {
+                    output::log(format, &format!("info: {}", client.start(replica).await?));
+                    continue;
+                }
+
+                for name in services.start_order(&[replica.to_owned()])? {
+                    // dependencies that are already running don't need to be started again
+                    if (&name != replica) && is_active(&name) {
+                        continue;
+                    }
+
+                    let process = Service::run(name.to_string(), services.clone())?;
+                    StateStore::set(&name, ServiceStatus::running(process.1.id()))?;
+                }
+            }
+
+            // return
+            Ok("Service scaled.")
+        }
         // info
-        Commands::Info { name } => match services.service_states.get(name) {
-            Some(_) => {
-                println!(
-                    "{}",
-                    Service::info(name.to_string(), services.service_states.clone())?
-                );
+        Commands::Info { select } => {
+            let names = select.names(&services)?;
+            let server = client.ping().await;
+            let states = StateStore::read();
+            let mut infos: Vec<ServiceInfo> = Vec::new();
+
+            for name in &names {
+                let info = if server {
+                    client.info(name).await
+                } else if !states.contains_key(name) {
+                    Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Service is not loaded. ({name})"),
+                    ))
+                } else {
+                    Service::info(name.to_string(), states.clone())
+                };
 
-                Ok("Finished.")
+                match info {
+                    // replicas of the same service are added up
+                    Ok(i) => {
+                        let service = model::replica_of(&i.name).0.to_string();
+
+                        match infos
+                            .iter_mut()
+                            .find(|s| model::replica_of(&s.name).0 == service)
+                        {
+                            Some(s) if !select.names.contains(&i.name) => s.add_replica(i),
+                            _ => infos.push(i),
+                        }
+                    }
+                    // services that aren't loaded are skipped when selecting more than one, and
+                    // so are the replicas of a service that aren't running
+                    Err(e) if select.is_explicit(name) => return Err(e),
+                    Err(_) if select.is_single() => (),
+                    Err(e) => output::log(format, &format!("warn: {e}")),
+                }
             }
-            None => Err(Error::new(ErrorKind::NotFound, "Service is not loaded.")),
-        },
+
+            // a single service is printed on its own
+            if select.is_single() {
+                output::print(format, "service", &infos[0])?;
+            } else {
+                output::print(format, "services", &infos)?;
+            }
+
+            Ok("Finished.")
+        }
         // info-all
         Commands::InfoAll {} => {
-            for service in &services.service_states {
-                if let Ok(i) = Service::info(service.0.to_string(), services.service_states.clone())
-                {
-                    println!("{i}");
+            let states = StateStore::read();
+            let mut names: Vec<&String> = states.keys().collect();
+            names.sort();
+
+            let mut infos = Vec::new();
+
+            for name in names {
+                if let Ok(i) = Service::info(name.to_string(), states.clone()) {
+                    infos.push(i);
+                }
+            }
+
+            // return
+            output::print(format, "services", &infos)?;
+            Ok("Finished.")
+        }
+        // status
+        Commands::Status {} => {
+            output::print(format, "services", &services.status(&StateStore::read()))?;
+            Ok("Finished.")
+        }
+        // events
+        Commands::Events { follow } => events(format, &client, *follow).await,
+        // timers
+        Commands::Timers {} => {
+            let states = StateStore::read();
+            let mut infos = Vec::new();
+
+            for name in services.names() {
+                let service = &services.services[&name];
+
+                if service.r#type != ServiceType::Timer {
+                    continue;
                 }
+
+                infos.push(timers::info(&name, service, &states));
             }
 
             // return
+            output::print(format, "timers", &infos)?;
             Ok("Finished.")
         }
         // track
-        Commands::Track { name } => match services.services.get(name) {
-            Some(_) => {
-                Service::observe(name.to_string(), services.service_states.clone()).await?;
+        Commands::Track { name } => match services.service(name) {
+            Some(service) => {
+                Service::observe(name.to_string(), StateStore::read()).await?;
 
-                services.service_states.remove(name);
-                ServicesConfiguration::update_config(services)?;
+                // services stopped with `sproc kill` have already run their `post_stop` hooks
+                if let Some(s) = StateStore::remove(name)? {
+                    if s.state != ServiceState::Stopping {
+                        let service = service.resolve(&services.vars)?;
+                        hooks::run_or_warn(name, &service, Hook::PostStop);
+                    }
+                }
 
                 // return
                 Ok("Service stopped.")
             }
             None => Err(Error::new(ErrorKind::NotFound, "Service does not exist.")),
         },
+        // logs
+        Commands::Logs {
+            name,
+            follow,
+            lines,
+            stderr,
+            lifecycle,
+        } => {
+            if services.service(name).is_none() {
+                return Err(Error::new(ErrorKind::NotFound, "Service does not exist."));
+            }
+
+            let stream = if *lifecycle {
+                LogStream::Lifecycle
+            } else if *stderr {
+                LogStream::Stderr
+            } else {
+                LogStream::Stdout
+            };
+
+            if *follow && format.is_structured() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Logs can only be followed with table output.",
+                ));
+            }
+
+            let logs = ServiceLogs::new(name);
+            let tail = logs.tail(stream, *lines)?;
+
+            if format.is_structured() {
+                let lines: Vec<&str> = tail.lines().collect();
+                output::print(format, "lines", &lines)?;
+                return Ok("Finished.");
+            }
+
+            println!("{tail}");
+
+            if *follow {
+                logs.follow(stream).await?;
+            }
+
+            Ok("Finished.")
+        }
         // pinned
-        Commands::Pinned {} => {
-            println!("{}", toml::to_string_pretty(&services).unwrap());
+        Commands::Pinned { explain } => {
+            if *explain {
+                let values = ServicesConfiguration::get_layers()?.explain();
+                output::print(format, "values", &values)?;
+                return Ok("Finished.");
+            }
+
+            // the configuration is always shown as TOML in tables
+            let format = match format {
+                OutputFormat::Table => OutputFormat::Toml,
+                format => format,
+            };
+
+            output::print(format, "config", &services.resolve()?)?;
             Ok("Finished.")
         }
         // merge
         Commands::Merge { path } => {
             // read file
-            let other_config = ServicesConfiguration::read(std::fs::read_to_string(path)?);
+            let other = Layers::load(&std::fs::read_to_string(path)?, path)?;
 
-            // merge and write
-            services.merge_config(other_config);
-            std::fs::write(
-                services.source.clone(),
-                toml::to_string_pretty(&services).unwrap(),
-            )?;
+            // merge and write (the source itself is changed, not its layers)
+            ServicesConfiguration::merge_file(&services.source, &other.value)?;
 
             // return
             Ok("Merged configuration. (source + other)")
@@ -290,11 +794,10 @@ async fn sproc<'a>() -> Result<&'a str> {
         // pull
         Commands::Pull { path } => {
             // read file
-            let other_config = ServicesConfiguration::read(std::fs::read_to_string(path)?);
+            let other = Layers::load(&std::fs::read_to_string(path)?, path)?;
 
             // merge and write
-            services.merge_config(other_config);
-            ServicesConfiguration::update_config(services)?;
+            ServicesConfiguration::merge_file(&ServicesConfiguration::pinned_path(), &other.value)?;
 
             // return
             Ok("Pulled configuration. (pinned + other)")
@@ -310,6 +813,8 @@ async fn sproc<'a>() -> Result<&'a str> {
 
             // post requests
             let client = reqwest::Client::new();
+            let mut installed = Vec::new();
+            let mut added = toml::Table::new();
 
             for name in names {
                 match client
@@ -349,16 +854,33 @@ async fn sproc<'a>() -> Result<&'a str> {
                         );
 
                         // push service
-                        services.services.insert(name.to_owned(), service);
+                        match toml::Value::try_from(&service) {
+                            Ok(v) => added.insert(name.to_owned(), v),
+                            Err(e) => {
+                                return Err(Error::new(ErrorKind::InvalidData, e.to_string()))
+                            }
+                        };
 
                         // log
-                        println!("info: installed service to pinned file: {}", name);
+                        output::log(
+                            format,
+                            &format!("info: installed service to pinned file: {name}"),
+                        );
+
+                        installed.push(name.to_owned());
                     }
                     Err(e) => return Err(Error::new(ErrorKind::NotConnected, e.to_string())),
                 }
             }
 
-            ServicesConfiguration::update_config(services.clone())?;
+            let mut other = toml::Table::new();
+            other.insert("services".to_string(), toml::Value::Table(added));
+            ServicesConfiguration::merge_file(&ServicesConfiguration::pinned_path(), &other)?;
+
+            if format.is_structured() {
+                output::print(format, "installed", &installed)?;
+            }
+
             Ok("Sent all requested requests.")
         }
         // uninstall
@@ -370,10 +892,20 @@ async fn sproc<'a>() -> Result<&'a str> {
                 ));
             }
 
+            let server = client.ping().await;
+
             for name in names {
-                if services.service_states.contains_key(name) {
-                    // kill service if it's running
-                    Service::kill(name.to_owned(), services.clone())?;
+                if let Some(s) = StateStore::get(name) {
+                    // kill service if it's running (through the server if it's running)
+                    if s.state.is_active() {
+                        if server {
+                            client.kill(name).await?;
+                        } else {
+                            Service::kill(name.to_owned(), services.clone())?;
+                        }
+                    }
+
+                    StateStore::remove(name)?;
                 }
 
                 // remove directory
@@ -382,22 +914,189 @@ async fn sproc<'a>() -> Result<&'a str> {
                 {
                     fs::remove_dir_all(format!("{home}/.config/xsu-apps/sproc/modules/{name}"))?
                 }
+            }
+
+            // remove services
+            let path = ServicesConfiguration::pinned_path();
+
+            for name in ServicesConfiguration::remove_from_file(&path, names)? {
+                if services.services.contains_key(&name) {
+                    output::log(
+                        format,
+                        &format!("warn: service \"{name}\" is inherited, it was not removed"),
+                    );
+                }
+            }
+
+            Ok("Finished.")
+        }
+    }
+}
+
+/// Print the events of a server (`sproc events`)
+async fn events<'a>(format: OutputFormat, client: &Client, follow: bool) -> Result<&'a str> {
+    if follow {
+        if format == OutputFormat::Toml {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Events can only be followed with JSON or table output.",
+            ));
+        }
+
+        // one event per line
+        client
+            .events(true, |event| match format {
+                OutputFormat::Table => println!("{}", event.line()),
+                _ => match serde_json::to_string(&event) {
+                    Ok(s) => println!("{s}"),
+                    Err(e) => eprintln!("{e}"),
+                },
+            })
+            .await?;
+
+        return Ok("Server stopped.");
+    }
+
+    let mut events = Vec::new();
+    client.events(false, |event| events.push(event)).await?;
+
+    if format.is_structured() {
+        output::print(format, "events", &events)?;
+    } else {
+        for event in events {
+            println!("{}", event.line());
+        }
+    }
+
+    Ok("Finished.")
+}
+
+/// Send a command to a remote server (`sproc --host {name}`)
+async fn remote<'a>(args: &Sproc, client: &Client) -> Result<&'a str> {
+    let format = args.output;
+
+    match &args.command {
+        // run, spawn
+        Commands::Run { select } | Commands::Spawn { select } => {
+            for name in select.remote_names()? {
+                output::log(format, &format!("info: {}", client.start(name).await?));
+            }
+
+            Ok("Started all requested services.")
+        }
+        // kill
+        Commands::Kill { select } => {
+            for name in select.remote_names()? {
+                client.kill(name).await?;
+            }
+
+            Ok("Stopped all given services.")
+        }
+        // info
+        Commands::Info { select } => {
+            let mut infos = Vec::new();
+
+            for name in select.remote_names()? {
+                match client.info(name).await {
+                    Ok(i) => infos.push(i),
+                    // services that aren't loaded are skipped when selecting more than one
+                    Err(e) if select.is_single() => return Err(e),
+                    Err(e) => output::log(format, &format!("warn: {e}")),
+                }
+            }
+
+            // a single service is printed on its own
+            if select.is_single() {
+                output::print(format, "service", &infos[0])?;
+            } else {
+                output::print(format, "services", &infos)?;
+            }
 
-                // remove service
-                services.services.remove(name);
+            Ok("Finished.")
+        }
+        // events
+        Commands::Events { follow } => events(format, client, *follow).await,
+        // logs
+        Commands::Logs {
+            name,
+            follow,
+            lines,
+            stderr,
+            lifecycle,
+        } => {
+            if *follow {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Logs can't be followed with --host.",
+                ));
+            }
+
+            let stream = if *lifecycle {
+                LogStream::Lifecycle
+            } else if *stderr {
+                LogStream::Stderr
+            } else {
+                LogStream::Stdout
+            };
+
+            let tail = client.logs(name, stream, *lines).await?;
+
+            if format.is_structured() {
+                let lines: Vec<&str> = tail.lines().collect();
+                output::print(format, "lines", &lines)?;
+            } else {
+                println!("{tail}");
+            }
+
+            Ok("Finished.")
+        }
+        // install
+        Commands::Install { registry, names } => {
+            if names.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Please provide at least 1 service name.",
+                ));
+            }
+
+            for name in names {
+                client.install(registry, name).await?;
+                output::log(
+                    format,
+                    &format!("info: installing service on remote: {name}"),
+                );
+            }
+
+            Ok("Sent all requested requests.")
+        }
+        // uninstall
+        Commands::Uninstall { names } => {
+            if names.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Please provide at least 1 service name.",
+                ));
+            }
+
+            for name in names {
+                client.uninstall(name).await?;
             }
 
-            ServicesConfiguration::update_config(services.clone())?;
             Ok("Finished.")
         }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "This command can't be used with --host.",
+        )),
     }
 }
 
 // fake main
 #[tokio::main]
 async fn main() {
-    match sproc().await {
-        Ok(s) => yes(s),
-        Err(e) => no(&e.to_string()),
+    let args = Sproc::parse();
+
+    if !output::finish(args.output, sproc(&args).await) {
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,223 @@
+//! Sproc service metrics (sampled by the server, served at `/metrics`)
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use sysinfo::System;
+
+use crate::model::{self, ServicesConfiguration};
+use crate::state::StateStore;
+
+/// Metrics sampling configuration for `sproc serve`
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MetricsConfiguration {
+    /// The time (in seconds) between samples (10 by default)
+    #[serde(default = "interval_default")]
+    pub interval: u64,
+    /// The number of samples kept for every service (60 by default)
+    #[serde(default = "history_default")]
+    pub history: usize,
+}
+
+fn interval_default() -> u64 {
+    10
+}
+
+fn history_default() -> usize {
+    60
+}
+
+impl Default for MetricsConfiguration {
+    fn default() -> Self {
+        Self {
+            interval: interval_default(),
+            history: history_default(),
+        }
+    }
+}
+
+/// The usage of a service process at a point in time
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Sample {
+    /// When the sample was taken (seconds since the unix epoch)
+    pub time: u64,
+    /// CPU usage (percent)
+    pub cpu: f32,
+    /// Resident memory (bytes)
+    pub rss: u64,
+    /// Open file descriptors (only available when the process can be inspected)
+    pub open_fds: Option<u64>,
+    /// Threads
+    pub threads: Option<u64>,
+    /// Seconds the process has been running for
+    pub uptime: u64,
+}
+
+/// Recent samples of every running service
+type History = HashMap<String, VecDeque<Sample>>;
+
+static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
+
+fn history() -> &'static Mutex<History> {
+    HISTORY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Count the entries of a directory in `/proc/{pid}`
+fn count_proc_entries(pid: u32, dir: &str) -> Option<u64> {
+    match std::fs::read_dir(format!("/proc/{pid}/{dir}")) {
+        Ok(entries) => Some(entries.count() as u64),
+        Err(_) => None,
+    }
+}
+
+/// Take a sample of every running service, replacing the history of services that stopped
+pub fn sample(sys: &mut System) {
+    sys.refresh_processes();
+
+    let config = ServicesConfiguration::get_config();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut history = match history().lock() {
+        Ok(h) => h,
+        Err(e) => e.into_inner(),
+    };
+
+    let mut sampled = Vec::new();
+
+    for (name, state) in StateStore::read() {
+        if !state.state.is_active() {
+            continue;
+        }
+
+        let process = match state.process(sys) {
+            Some(p) => p,
+            None => continue,
+        };
+
+        let samples = history.entry(name.clone()).or_default();
+
+        samples.push_back(Sample {
+            time,
+            cpu: process.cpu_usage(),
+            rss: process.memory(),
+            open_fds: count_proc_entries(state.pid, "fd"),
+            threads: count_proc_entries(state.pid, "task"),
+            uptime: process.run_time(),
+        });
+
+        while samples.len() > config.server.metrics.history.max(1) {
+            samples.pop_front();
+        }
+
+        sampled.push(name);
+    }
+
+    history.retain(|name, _| sampled.contains(name));
+}
+
+/// Sample every running service on the configured interval
+pub async fn sample_task() {
+    let mut sys = System::new();
+
+    // cpu usage is measured between two refreshes
+    sys.refresh_processes();
+
+    loop {
+        let interval = ServicesConfiguration::get_config().server.metrics.interval;
+        tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+        sample(&mut sys);
+    }
+}
+
+/// Get the recent samples of every running service
+pub fn get_history() -> History {
+    match history().lock() {
+        Ok(h) => h.clone(),
+        Err(e) => e.into_inner().clone(),
+    }
+}
+
+/// A Prometheus metric (name, help text, and how it is read from a sample)
+type Metric = (&'static str, &'static str, fn(&Sample) -> Option<f64>);
+
+/// Escape a Prometheus label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render the latest sample of every running service in the Prometheus text format
+///
+/// Every metric is labeled by the service name and its tags (comma separated).
+pub fn prometheus() -> String {
+    let config = ServicesConfiguration::get_config();
+    let history = get_history();
+
+    let mut names: Vec<&String> = history.keys().collect();
+    names.sort();
+
+    let metrics: [Metric; 5] = [
+        (
+            "sproc_cpu_usage_percent",
+            "CPU usage of the service process (percent)",
+            |s| Some(s.cpu as f64),
+        ),
+        (
+            "sproc_memory_rss_bytes",
+            "Resident memory of the service process",
+            |s| Some(s.rss as f64),
+        ),
+        (
+            "sproc_open_fds",
+            "Open file descriptors of the service process",
+            |s| s.open_fds.map(|v| v as f64),
+        ),
+        ("sproc_threads", "Threads of the service process", |s| {
+            s.threads.map(|v| v as f64)
+        }),
+        (
+            "sproc_uptime_seconds",
+            "Time the service process has been running for",
+            |s| Some(s.uptime as f64),
+        ),
+    ];
+
+    let mut out = String::new();
+
+    for (metric, help, value) in metrics {
+        out.push_str(&format!("# HELP {metric} {help}\n# TYPE {metric} gauge\n"));
+
+        for name in &names {
+            let sample = match history[*name].back() {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let value = match value(sample) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let tags = match config.service(name) {
+                Some(s) => s.tags.join(","),
+                None => String::new(),
+            };
+
+            out.push_str(&format!(
+                "{metric}{{service=\"{}\",replica=\"{}\",tags=\"{}\"}} {value}\n",
+                escape(model::replica_of(name).0),
+                model::replica_of(name).1,
+                escape(&tags)
+            ));
+        }
+    }
+
+    out
+}
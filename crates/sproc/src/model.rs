@@ -1,15 +1,33 @@
 //! Sproc process management (service handling)
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as DeError, Deserialize, Deserializer, Serialize};
 use std::{
-    collections::HashMap,
-    env,
+    collections::{HashMap, VecDeque},
+    env, fmt,
     io::{BufRead, BufReader, Error, ErrorKind, Result},
+    os::unix::process::CommandExt,
+    path::PathBuf,
     process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use sysinfo::{Pid, System};
+use sysinfo::{Pid, Process, System};
+use toml_edit::{DocumentMut, Item};
 use xsu_util::fs;
 
-pub type ServiceStates = HashMap<String, (ServiceState, u32)>;
+use crate::events::{self, EventKind};
+use crate::health::{self, HealthCheck};
+use crate::hooks::{self, Hook};
+use crate::layers::Layers;
+use crate::limits::ResourceLimits;
+use crate::logs::{LogConfiguration, LogStream, ServiceLogs};
+use crate::metrics::MetricsConfiguration;
+use crate::state::StateStore;
+use crate::{shell, timers, unix, vars, watch};
+
+pub type ServiceStates = HashMap<String, ServiceStatus>;
 
 /// [`Service`] metadata/extra information that isn't needed to run the service
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -50,6 +68,8 @@ pub enum ServiceType {
     Service,
     /// A service that does not run in the background and does not track PID
     Application,
+    /// A service that is run in the background on a `schedule` (HTTP server required)
+    Timer,
 }
 
 impl Default for ServiceType {
@@ -58,6 +78,80 @@ impl Default for ServiceType {
     }
 }
 
+/// When a [`Service`] is restarted after it exits
+#[derive(Clone, Serialize, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Never restart the service
+    #[default]
+    Never,
+    /// Restart the service when it exits with a non-zero code (or is killed by a signal)
+    OnFailure,
+    /// Restart the service whenever it exits
+    Always,
+    /// Restart the service whenever it exits, and keep it stopped across server restarts once it
+    /// has been stopped with `sproc kill`
+    UnlessStopped,
+}
+
+impl RestartPolicy {
+    /// If a service exiting with the given success should be restarted
+    pub fn should_restart(&self, success: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+        }
+    }
+
+    /// If a service is started again when the server starts, given if it was stopped with
+    /// `sproc kill` (instead of running, restarting or failing when the server stopped)
+    pub fn should_resurrect(&self, stopped: bool) -> bool {
+        match self {
+            RestartPolicy::Never | RestartPolicy::OnFailure => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::UnlessStopped => !stopped,
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+            RestartPolicy::UnlessStopped => "unless-stopped",
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for RestartPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        // `restart` used to be a bool, so we still need to accept that
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bool(bool),
+            Name(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Bool(true) => Ok(Self::Always),
+            Repr::Bool(false) => Ok(Self::Never),
+            Repr::Name(name) => match name.as_str() {
+                "never" => Ok(Self::Never),
+                "on-failure" => Ok(Self::OnFailure),
+                "always" => Ok(Self::Always),
+                "unless-stopped" => Ok(Self::UnlessStopped),
+                _ => Err(D::Error::custom(format!(
+                    "unknown restart policy \"{name}\", expected one of never, on-failure, always, unless-stopped"
+                ))),
+            },
+        }
+    }
+}
+
 /// A single executable service
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Service {
@@ -65,26 +159,440 @@ pub struct Service {
     #[serde(default)]
     pub r#type: ServiceType,
     /// What command is run to start the service
+    ///
+    /// Split into arguments like a shell would (unless `args` is set or `shell` is enabled).
     pub command: String,
+    /// Arguments passed to `command` as-is (`command` is not split when this is set)
+    pub args: Option<Vec<String>>,
+    /// If `command` is run with `/bin/sh -c` (so shell features like pipes can be used)
+    #[serde(default)]
+    pub shell: bool,
     /// Where the `command` is run
     pub working_directory: String,
     /// Environment variables map
     pub environment: Option<HashMap<String, String>>,
-    /// If the service should restart automatically when exited (HTTP server required)
+    /// Dotenv files loaded into the environment (relative to `working_directory`), in order
+    ///
+    /// Variables in `environment` override the variables loaded from these files.
+    #[serde(default)]
+    pub env_file: Vec<String>,
+    /// When the service should restart automatically after it exits (HTTP server required)
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// The delay (in milliseconds) before the service is restarted, doubled after every restart
+    #[serde(default = "restart_delay_default")]
+    pub restart_delay: u64,
+    /// The maximum delay (in milliseconds) before the service is restarted
+    #[serde(default = "restart_delay_max_default")]
+    pub restart_delay_max: u64,
+    /// The number of restarts allowed within `restart_window` before the service is marked as failed
+    #[serde(default = "max_restarts_default")]
+    pub max_restarts: u32,
+    /// The window (in seconds) that `max_restarts` is counted in
+    #[serde(default = "restart_window_default")]
+    pub restart_window: u64,
+    /// If the service is started when the server starts
+    #[serde(default)]
+    pub autostart: bool,
+    /// Services that must be started before this service (and stopped after it)
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// The signal sent to the service's process group to stop it
+    #[serde(default = "stop_signal_default")]
+    pub stop_signal: String,
+    /// The time (in seconds) to wait for the service to stop before it is killed with `SIGKILL`
+    #[serde(default = "stop_timeout_default")]
+    pub stop_timeout: u64,
+    /// Commands run (with `sh -c`) before the service is started, the service isn't started if
+    /// one of them fails
+    #[serde(default)]
+    pub pre_start: Vec<String>,
+    /// Commands run after the service is started
+    #[serde(default)]
+    pub post_start: Vec<String>,
+    /// Commands run before the service is stopped with `sproc kill`
+    #[serde(default)]
+    pub pre_stop: Vec<String>,
+    /// Commands run after the service stopped
+    #[serde(default)]
+    pub post_stop: Vec<String>,
+    /// The time (in seconds) a hook command can run for before it is killed
+    #[serde(default = "hook_timeout_default")]
+    pub hook_timeout: u64,
+    /// Files (glob patterns, relative to `working_directory`) that restart the service when they
+    /// change (HTTP server required)
+    #[serde(default)]
+    pub watch: Vec<String>,
+    /// Files (glob patterns) that are never watched
     #[serde(default)]
-    pub restart: bool,
+    pub watch_ignore: Vec<String>,
+    /// The time (in milliseconds) files must stop changing for before the service is restarted
+    #[serde(default = "watch_debounce_default")]
+    pub watch_debounce: u64,
+    /// Health probe run by the server while the service is running
+    pub health: Option<HealthCheck>,
+    /// Log file limits (`~/.config/xsu-apps/sproc/logs/:name`)
+    #[serde(default)]
+    pub logs: LogConfiguration,
+    /// Resource limits applied to the service process
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// The user the service is run as (the server must be run as root to use this)
+    pub user: Option<String>,
+    /// The group the service is run as (the primary group of `user` by default)
+    pub group: Option<String>,
+    /// Supplementary groups the service is run with (along with the groups of `user`)
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// When a timer is run (cron expression, `min hour day month weekday` or with seconds)
+    pub schedule: Option<String>,
+    /// If a timer run is skipped while the previous run is still running
+    #[serde(default = "skip_overlapping_default")]
+    pub skip_overlapping: bool,
+    /// Tags used to select services in bulk (`--tag`)
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The number of copies of the service that are run (`name`, `name#1`, `name#2`, ...)
+    #[serde(default = "replicas_default")]
+    pub replicas: u32,
+    /// The port of the first replica, `PORT` is set to this plus the index of the replica
+    pub base_port: Option<u16>,
     /// Metadata
     #[serde(default)]
     pub metadata: ServiceMetadata,
+    /// The `vars` of the configuration, used by `command` and hooks (set by [`Service::resolve`])
+    #[serde(skip)]
+    pub vars: HashMap<String, String>,
+}
+
+fn stop_signal_default() -> String {
+    "SIGTERM".to_string()
+}
+
+fn stop_timeout_default() -> u64 {
+    10
+}
+
+fn hook_timeout_default() -> u64 {
+    30
+}
+
+fn watch_debounce_default() -> u64 {
+    500
+}
+
+fn replicas_default() -> u32 {
+    1
+}
+
+fn skip_overlapping_default() -> bool {
+    true
+}
+
+fn restart_delay_default() -> u64 {
+    1000
+}
+
+fn restart_delay_max_default() -> u64 {
+    30000
+}
+
+fn max_restarts_default() -> u32 {
+    5
+}
+
+fn restart_window_default() -> u64 {
+    60
+}
+
+/// If a service name is the name of a template service (`name@`)
+pub fn is_template(name: &str) -> bool {
+    name.ends_with('@')
+}
+
+/// If `instance` can be used as the instance of a template (`name@instance`)
+///
+/// Instances are used in file names and commands, so they can only contain letters, digits,
+/// `.`, `_` and `-`.
+pub fn is_instance_name(instance: &str) -> bool {
+    !instance.is_empty()
+        && instance
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// If `name` can be used as the name of a service (`#` is only used in the names of replicas)
+pub fn is_service_name(name: &str) -> bool {
+    !name.contains('#')
+}
+
+/// Make sure every service name can be used (see [`is_service_name`])
+pub fn check_service_names<'a>(names: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    match names.into_iter().find(|n| !is_service_name(n)) {
+        Some(name) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Service names can't contain \"#\", it's used in the names of replicas. ({name})"
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Split the name of a replica (`name#index`) into the name of its service and its index
+///
+/// The first replica of a service has the same name as the service (and an index of `0`).
+/// Indexes must be written the way [`replica_names`] writes them (`web#01` isn't a replica).
+pub fn replica_of(name: &str) -> (&str, u32) {
+    let (service, index) = match name.rsplit_once('#') {
+        Some(s) => s,
+        None => return (name, 0),
+    };
+
+    if index.starts_with('0') || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return (name, 0);
+    }
+
+    match index.parse::<u32>() {
+        Ok(i) => (service, i),
+        Err(_) => (name, 0),
+    }
+}
+
+/// Get the names of the first `count` replicas of a service
+pub fn replica_names(name: &str, count: u32) -> Vec<String> {
+    (0..count)
+        .map(|i| match i {
+            0 => name.to_string(),
+            i => format!("{name}#{i}"),
+        })
+        .collect()
 }
 
 impl Service {
+    /// Get a copy of a template service (`name@`) for an instance, with every `%i` in `command`,
+    /// `args`, hooks, `working_directory`, `env_file` and `environment` replaced by `instance`
+    pub fn instantiate(&self, instance: &str) -> Service {
+        let replace = |s: &String| s.replace("%i", instance);
+
+        let mut service = self.clone();
+        service.command = replace(&self.command);
+        service.args = self.args.as_ref().map(|a| a.iter().map(replace).collect());
+        service.working_directory = replace(&self.working_directory);
+        service.env_file = self.env_file.iter().map(replace).collect();
+        service.environment = self
+            .environment
+            .as_ref()
+            .map(|e| e.iter().map(|(k, v)| (k.to_owned(), replace(v))).collect());
+
+        service.pre_start = self.pre_start.iter().map(replace).collect();
+        service.post_start = self.post_start.iter().map(replace).collect();
+        service.pre_stop = self.pre_stop.iter().map(replace).collect();
+        service.post_stop = self.post_stop.iter().map(replace).collect();
+
+        service
+    }
+
+    /// Get a copy of the service for one of its replicas, with `REPLICA_INDEX` (and `PORT`, if
+    /// `base_port` is set) added to its environment
+    pub fn replica(&self, index: u32) -> Service {
+        let mut service = self.clone();
+        let environment = service.environment.get_or_insert_with(HashMap::new);
+
+        environment.insert("REPLICA_INDEX".to_string(), index.to_string());
+
+        if let Some(port) = self.base_port {
+            environment.insert("PORT".to_string(), (port as u32 + index).to_string());
+        }
+
+        service
+    }
+
+    /// Get a copy of the service with its `env_file`s loaded into `environment`, and every
+    /// `${VAR}` in `args`, `working_directory` and `environment` interpolated
+    ///
+    /// Variables are looked up in `vars`, and then the environment of this process (`args` can
+    /// also use the service's environment). Variables in `working_directory` and `env_file`
+    /// paths must be set. Variables in `command` and hooks are expanded when they are run
+    /// instead, see [`Service::variable`].
+    pub fn resolve(&self, vars: &HashMap<String, String>) -> Result<Service> {
+        let lookup = |var: &str| match vars.get(var) {
+            Some(v) => Some(v.to_owned()),
+            None => env::var(var).ok(),
+        };
+
+        let mut service = self.clone();
+        service.working_directory = vars::interpolate_strict(&self.working_directory, &lookup)?;
+
+        // env files are loaded first, so `environment` can override them
+        let mut environment: HashMap<String, String> = HashMap::new();
+
+        for path in &self.env_file {
+            let mut path = vars::interpolate_strict(path, &lookup)?;
+
+            if !path.starts_with('/') {
+                path = format!("{}/{path}", service.working_directory);
+            }
+
+            let contents = match std::fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(e) => {
+                    return Err(Error::new(
+                        e.kind(),
+                        format!("Failed to read env file \"{path}\": {e}"),
+                    ))
+                }
+            };
+
+            // env files can use the variables of the files loaded before them
+            let loaded =
+                vars::parse_env_file(&contents, &|var: &str| match environment.get(var) {
+                    Some(v) => Some(v.to_owned()),
+                    None => lookup(var),
+                })?;
+
+            environment.extend(loaded);
+        }
+
+        if let Some(ref env) = self.environment {
+            for (key, value) in env {
+                environment.insert(key.to_owned(), vars::interpolate(value, &lookup)?);
+            }
+        }
+
+        // interpolate args
+        let lookup = |var: &str| match environment.get(var) {
+            Some(v) => Some(v.to_owned()),
+            None => lookup(var),
+        };
+
+        if let Some(ref args) = self.args {
+            let mut interpolated = Vec::new();
+
+            for arg in args {
+                interpolated.push(vars::interpolate(arg, &lookup)?);
+            }
+
+            service.args = Some(interpolated);
+        }
+
+        if self.environment.is_some() || !environment.is_empty() {
+            service.environment = Some(environment);
+        }
+
+        service.vars = vars.clone();
+        Ok(service)
+    }
+
+    /// Get a copy of a resolved service with the variables in `command` and hooks expanded, to
+    /// show the service the way it's run
+    ///
+    /// Commands that aren't run by a shell are split first (and their words quoted again), so
+    /// expanded values are shown as the single words they are. The copy is only for showing,
+    /// it should never be run.
+    pub fn expanded(&self) -> Result<Service> {
+        let env = self.environment.clone().unwrap_or_default();
+        let lookup = |var: &str| self.variable(var, &env);
+
+        let mut service = self.clone();
+
+        match (self.shell, &self.args) {
+            (false, None) => {
+                let words: Vec<String> = shell::split(&self.command, lookup)?
+                    .iter()
+                    .map(|w| shell::quote(w))
+                    .collect();
+
+                service.command = words.join(" ");
+            }
+            // the command is the program itself
+            (false, Some(_)) => (),
+            (true, _) => service.command = vars::interpolate(&self.command, &lookup)?,
+        }
+
+        for hook in [
+            &mut service.pre_start,
+            &mut service.post_start,
+            &mut service.pre_stop,
+            &mut service.post_stop,
+        ] {
+            for command in hook.iter_mut() {
+                *command = vars::interpolate(command, &lookup)?;
+            }
+        }
+
+        Ok(service)
+    }
+
+    /// Look up a variable used in `command` or a hook: in `env` (the environment of the
+    /// process) first, and then in `vars` and the environment of this process
+    pub fn variable(&self, name: &str, env: &HashMap<String, String>) -> Option<String> {
+        match env.get(name).or(self.vars.get(name)) {
+            Some(v) => Some(v.to_owned()),
+            None => env::var(name).ok(),
+        }
+    }
+
+    /// Get the `vars` used by a command that is run with `sh -c`
+    ///
+    /// They are given to the shell as environment variables, so the shell expands them itself
+    /// (and their values are never parsed as shell code). Variables set in `env` (the
+    /// environment of the process) are left to it.
+    pub fn shell_vars(
+        &self,
+        command: &str,
+        env: &HashMap<String, String>,
+    ) -> HashMap<String, String> {
+        shell::variables(command)
+            .into_iter()
+            .filter(|name| !env.contains_key(name))
+            .filter_map(|name| {
+                let value = self.vars.get(&name)?.to_owned();
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// Get the user and groups the service should be run as (`None` if they aren't changed)
+    pub fn credentials(&self) -> Result<Option<unix::Credentials>> {
+        if self.user.is_none() && self.group.is_none() && self.groups.is_empty() {
+            return Ok(None);
+        }
+
+        let user = match self.user {
+            Some(ref name) => Some(unix::lookup_user(name)?),
+            None => None,
+        };
+
+        let gid = match self.group {
+            Some(ref name) => Some(unix::lookup_group(name)?),
+            None => user.as_ref().map(|a| a.gid),
+        };
+
+        // the user keeps the groups it is a member of (only root can set them), and `groups`
+        // are added to them
+        let mut groups = match (&user, gid) {
+            (Some(u), Some(g)) if unsafe { libc::geteuid() } == 0 => unix::user_groups(u, g)?,
+            _ => Vec::new(),
+        };
+
+        for name in &self.groups {
+            let group = unix::lookup_group(name)?;
+
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+
+        Ok(Some(unix::Credentials { user, gid, groups }))
+    }
+
     /// Spawn service process
     pub fn run(name: String, config: ServicesConfiguration) -> Result<(Service, Child)> {
         // check current state
-        if let Some(s) = config.service_states.get(&name) {
+        if let Some(s) = StateStore::get(&name) {
             // make sure service isn't already running
-            if s.0 == ServiceState::Running {
+            if s.state.is_active() {
                 return Err(Error::new(
                     ErrorKind::AlreadyExists,
                     format!("Service is already running. ({name})"),
@@ -92,7 +600,7 @@ impl Service {
             }
         };
 
-        let service = match config.services.get(&name) {
+        let service = match config.service(&name) {
             Some(s) => s,
             None => {
                 return Err(Error::new(
@@ -102,30 +610,132 @@ impl Service {
             }
         };
 
-        // create command
-        println!("info: cmd: {}", service.command);
-        let command_split: Vec<&str> = service.command.split(" ").collect();
-        let mut cmd = Command::new(command_split.get(0).unwrap());
+        // spawn
+        let service = service.resolve(&config.vars)?;
+        let process = service.start_process(&name)?;
+        Ok((service, process))
+    }
+
+    /// Create the command that starts the service
+    ///
+    /// Variables in `command` are expanded with [`Service::variable`] (or by the shell when
+    /// `shell` is enabled), `env` is the environment of the process.
+    pub fn command(&self, name: &str, env: &HashMap<String, String>) -> Result<Command> {
+        if self.shell {
+            // extra args are given to the shell as positional parameters
+            let mut cmd = Command::new("/bin/sh");
+            cmd.arg("-c").arg(&self.command).arg(name);
+            cmd.envs(self.shell_vars(&self.command, env));
+
+            if let Some(ref args) = self.args {
+                cmd.args(args);
+            }
 
-        for arg in command_split.iter().skip(1) {
-            cmd.arg(arg);
+            return Ok(cmd);
         }
 
-        if let Some(env) = service.environment.clone() {
-            for var in env {
-                cmd.env(var.0, var.1);
+        if let Some(ref args) = self.args {
+            let mut cmd = Command::new(&self.command);
+            cmd.args(args);
+            return Ok(cmd);
+        }
+
+        let words = shell::split(&self.command, |var| self.variable(var, env))?;
+
+        match words.split_first() {
+            Some((program, args)) => {
+                let mut cmd = Command::new(program);
+                cmd.args(args);
+                Ok(cmd)
             }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Service command is empty. ({name})"),
+            )),
         }
+    }
 
-        cmd.current_dir(&service.working_directory);
+    /// Get the environment variables processes of the service are given
+    ///
+    /// The environment matches the user the service is run as (unless it is overridden).
+    pub fn process_environment(
+        &self,
+        credentials: &Option<unix::Credentials>,
+    ) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+
+        if let Some(ref c) = credentials {
+            if let Some(ref user) = c.user {
+                env.insert("HOME".to_string(), user.home.clone());
+                env.insert("USER".to_string(), user.name.clone());
+                env.insert("LOGNAME".to_string(), user.name.clone());
+            }
+        }
+
+        if let Some(ref environment) = self.environment {
+            env.extend(environment.clone());
+        }
+
+        env
+    }
+
+    /// Spawn a new service process (without checking if the service is already running)
+    ///
+    /// `pre_start` hooks are run first (nothing is started if one fails), and `post_start` hooks
+    /// once the process is started.
+    pub fn start_process(&self, name: &str) -> Result<Child> {
+        hooks::run(name, self, Hook::PreStart)?;
+
+        let credentials = self.credentials()?;
+        let env = self.process_environment(&credentials);
+
+        // create command
+        println!("info: cmd: {}", self.command);
+        let mut cmd = self.command(name, &env)?;
+        cmd.envs(env);
+
+        cmd.current_dir(&self.working_directory);
+
+        // apply resource limits and switch user in the new process (before the command is
+        // executed)
+        let limits = self.limits.clone();
+
+        if !limits.is_empty() || credentials.is_some() {
+            unsafe {
+                cmd.pre_exec(move || {
+                    limits.apply()?;
+
+                    match credentials {
+                        Some(ref c) => c.apply(),
+                        None => Ok(()),
+                    }
+                });
+            }
+        }
+
+        // capture output and put the service in its own process group (so it can be stopped along
+        // with everything it starts)
+        // applications are run in the foreground, so they keep the terminal
+        let logs = ServiceLogs::new(name);
+
+        if self.r#type != ServiceType::Application {
+            cmd.process_group(0);
+
+            cmd.stdout(logs.stdio(LogStream::Stdout, &self.logs)?);
+            cmd.stderr(logs.stdio(LogStream::Stderr, &self.logs)?);
+        }
 
         // spawn
-        Ok((service.to_owned(), cmd.spawn()?))
+        let child = cmd.spawn()?;
+        logs.event(&format!("started (pid {})", child.id()));
+        events::publish(name, EventKind::Started { pid: child.id() });
+        hooks::run_or_warn(name, self, Hook::PostStart);
+        Ok(child)
     }
 
     /// Kill service process
     pub fn kill(name: String, config: ServicesConfiguration) -> Result<()> {
-        let s = match config.service_states.get(&name) {
+        let s = match StateStore::get(&name) {
             Some(s) => s,
             None => {
                 return Err(Error::new(
@@ -135,60 +745,84 @@ impl Service {
             }
         };
 
-        if s.0 != ServiceState::Running {
+        if s.state == ServiceState::Restarting {
+            // there is no process to kill, the server just needs to know not to start it again
+            StateStore::stop(&name)?;
+            return Ok(());
+        }
+
+        if !s.state.is_active() {
             return Err(Error::new(
                 ErrorKind::NotConnected,
                 "Service is not running.",
             ));
         }
 
-        let mut config_c = config.clone();
-        let service = match config_c.services.get_mut(&name) {
-            Some(s) => s,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::NotFound,
-                    format!("Service does not exist. ({name})"),
-                ))
-            }
-        };
-
         // stop service
         let sys = System::new_all();
 
-        match sys.process(Pid::from(s.1 as usize)) {
-            Some(process) => {
-                let supposed_to_restart = service.restart.clone();
-
-                // if service is supposed to restart, toggle off and update config
-                if supposed_to_restart {
-                    // we must do this so threads that will restart this service don't
-                    service.restart = false;
-                    ServicesConfiguration::update_config(config_c.clone())?;
-                }
+        match s.process(&sys) {
+            Some(_) => {
+                // mark service as stopping so the server doesn't restart it
+                StateStore::update(|states| {
+                    if let Some(s) = states.get_mut(&name) {
+                        s.state = ServiceState::Stopping;
+                    }
+                })?;
 
                 // kill process
-                process.kill();
-                std::thread::sleep(std::time::Duration::from_secs(1)); // wait for 1s so the server can catch up
-
-                // if service was previously supposed to restart, re-enable restart
-                if supposed_to_restart {
-                    // set config back to original form
-                    ServicesConfiguration::update_config(config.clone())?;
+                match config.service(&name) {
+                    Some(service) => {
+                        // hooks still run if the environment can't be loaded anymore
+                        let service = service.resolve(&config.vars).unwrap_or(service);
+
+                        hooks::run_or_warn(&name, &service, Hook::PreStop);
+                        Service::stop_process(s.pid, &service)?;
+                        ServiceLogs::new(&name).event("stopped with `sproc kill`");
+                        hooks::run_or_warn(&name, &service, Hook::PostStop);
+                    }
+                    None => unix::signal_group(s.pid, libc::SIGKILL)?,
                 }
 
-                // return
+                StateStore::stop(&name)?;
                 Ok(())
             }
-            None => Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Failed to get process from PID. ({name})"),
-            )),
+            None => Err(StateStore::remove_stale(&name)),
         }
     }
 
+    /// Kill service process in a blocking task, so stopping it (which can take up to its
+    /// `stop_timeout`, along with its hooks) doesn't hold up the async runtime
+    pub async fn kill_blocking(name: String, config: ServicesConfiguration) -> Result<()> {
+        tokio::task::spawn_blocking(move || Service::kill(name, config)).await?
+    }
+
+    /// Stop a service process (and every process in its group) with the service's
+    /// `stop_signal`, killing it if it is still running after `stop_timeout`
+    pub fn stop_process(pid: u32, service: &Service) -> Result<()> {
+        unix::signal_group(pid, unix::parse_signal(&service.stop_signal)?)?;
+
+        // wait for process group to exit
+        let deadline = Instant::now() + Duration::from_secs(service.stop_timeout);
+
+        while unix::group_alive(pid) {
+            if Instant::now() >= deadline {
+                println!(
+                    "warn: process {pid} did not stop after {}s, killing",
+                    service.stop_timeout
+                );
+
+                return unix::signal_group(pid, libc::SIGKILL);
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(())
+    }
+
     /// Get service process info
-    pub fn info(name: String, service_states: ServiceStates) -> Result<String> {
+    pub fn info(name: String, service_states: ServiceStates) -> Result<ServiceInfo> {
         let s = match service_states.get(&name) {
             Some(s) => s,
             None => {
@@ -199,32 +833,41 @@ impl Service {
             }
         };
 
-        if s.0 != ServiceState::Running {
-            return Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Service is not running. ({name})"),
-            ));
+        let mut info = ServiceInfo {
+            name: name.to_string(),
+            state: s.state.clone(),
+            restarts: s.restarts,
+            exit_code: s.exit_code,
+            pid: None,
+            memory: None,
+            cpu: None,
+            status: None,
+            running_for_seconds: None,
+            replicas: None,
+            limits: match ServicesConfiguration::get_config().service(&name) {
+                Some(service) if !service.limits.is_empty() => Some(service.limits),
+                _ => None,
+            },
+        };
+
+        if !s.state.is_active() {
+            // not running, only the recorded state is available
+            return Ok(info);
         }
 
         // get service info
         let sys = System::new_all();
 
-        if let Some(process) = sys.process(Pid::from(s.1 as usize)) {
-            let info = ServiceInfo {
-                name: name.to_string(),
-                pid: process.pid().to_string().parse().unwrap(),
-                memory: process.memory(),
-                cpu: process.cpu_usage(),
-                status: process.status().to_string(),
-                running_for_seconds: process.run_time(),
-            };
+        if let Some(process) = s.process(&sys) {
+            info.pid = Some(process.pid().to_string().parse().unwrap());
+            info.memory = Some(process.memory());
+            info.cpu = Some(process.cpu_usage());
+            info.status = Some(process.status().to_string());
+            info.running_for_seconds = Some(process.run_time());
 
-            Ok(toml::to_string_pretty(&info).unwrap())
+            Ok(info)
         } else {
-            Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Failed to get process from PID. ({name})"),
-            ))
+            Err(StateStore::remove_stale(&name))
         }
     }
 
@@ -242,7 +885,7 @@ impl Service {
             }
         };
 
-        if s.0 != ServiceState::Running {
+        if !s.state.is_active() {
             return Err(Error::new(
                 ErrorKind::NotConnected,
                 format!("Service is not running. ({name})"),
@@ -252,76 +895,260 @@ impl Service {
         // get service
         let sys = System::new_all();
 
-        if let Some(process) = sys.process(Pid::from(s.1 as usize)) {
+        if let Some(process) = s.process(&sys) {
             // wait for process to stop
             process.wait();
             Ok(())
         } else {
-            Err(Error::new(
-                ErrorKind::NotConnected,
-                format!("Failed to get process from PID. ({name})"),
-            ))
+            Err(StateStore::remove_stale(&name))
         }
     }
 
-    /// Start and observe a service
-    async fn wait(name: String, config: &mut ServicesConfiguration) -> Result<()> {
-        // start service
-        let process = match Service::run(name.clone(), config.clone()) {
-            Ok(p) => p,
-            Err(e) => return Err(e),
-        };
+    /// Start a service and record its state
+    fn start(name: &String, restarts: u32) -> Result<Child> {
+        let config = ServicesConfiguration::get_config();
+        let process = Service::run(name.clone(), config)?;
+
+        // update state
+        // services with a health check are only running once their first probe passes
+        StateStore::set(
+            name,
+            ServiceStatus {
+                state: match process.0.health {
+                    Some(_) => ServiceState::Starting,
+                    None => ServiceState::Running,
+                },
+                restarts,
+                ..ServiceStatus::running(process.1.id())
+            },
+        )?;
+
+        Ok(process.1)
+    }
 
-        // update config
-        config
-            .service_states
-            .insert(name.to_string(), (ServiceState::Running, process.1.id()));
+    /// Start a service and record its state in a blocking task, so its hooks (which can run for
+    /// up to `hook_timeout` each) don't hold up the async runtime
+    async fn start_blocking(name: &str, restarts: u32) -> Result<Child> {
+        let name = name.to_owned();
+        tokio::task::spawn_blocking(move || Service::start(&name, restarts)).await?
+    }
 
-        ServicesConfiguration::update_config(config.clone()).expect("Failed to update config");
-        Service::observe(name.clone(), config.service_states.clone())
-            .await
-            .expect("Failed to observe service");
+    /// Record the state of a service that exited, unless it was stopped with `sproc kill`
+    ///
+    /// Returns `false` if the service was stopped (and its state was removed).
+    fn record(name: &String, state: ServiceState, restarts: u32, exit_code: Option<i32>) -> bool {
+        StateStore::update(|states| {
+            match states.get(name) {
+                Some(s) if s.state != ServiceState::Stopping => (),
+                _ => {
+                    // service was stopped with `sproc kill`
+                    states.remove(name);
+                    return false;
+                }
+            }
 
-        Ok(())
+            states.insert(
+                name.to_string(),
+                ServiceStatus {
+                    state,
+                    restarts,
+                    exit_code,
+                    ..Default::default()
+                },
+            );
+
+            true
+        })
+        .expect("Failed to update state")
     }
 
-    /// [`Service::wait`] in a new task
-    pub async fn spawn(name: String) -> Result<()> {
-        // spawn task
+    /// Observe a started service in a new task, restarting it when it exits (based on its
+    /// [`RestartPolicy`])
+    fn supervise(name: String, mut child: Child) {
         tokio::task::spawn(async move {
+            let mut restarts: u32 = 0;
+            let mut history: VecDeque<Instant> = VecDeque::new(); // restart times within the window
+            let mut delay_exponent: u32 = 0;
+            let restart = Arc::new(AtomicBool::new(false)); // set when watched files change
+            let logs = ServiceLogs::new(&name);
+
             loop {
-                // pull config from file
-                let mut config = ServicesConfiguration::get_config();
+                // probe health and watch files while running
+                tokio::task::spawn(health::monitor(name.clone(), child.id()));
+                tokio::task::spawn(watch::monitor(name.clone(), child.id(), restart.clone()));
 
-                // start service
-                Service::wait(name.clone(), &mut config)
+                // wait for service to stop
+                let started = Instant::now();
+                let status = tokio::task::spawn_blocking(move || child.wait())
                     .await
-                    .expect("Failed to wait for service");
+                    .expect("Failed to observe service")
+                    .expect("Failed to observe service");
 
                 // pull real config
-                // we have to do this so we don't restart if it was disabled while the service was running
-                let mut config = ServicesConfiguration::get_config();
-                let service = match config.services.get(&name) {
+                // we have to do this so we don't restart if it was changed while the service was running
+                let config = ServicesConfiguration::get_config();
+                let service = match config.service(&name) {
                     Some(s) => s,
-                    None => return,
+                    None => {
+                        let _ = StateStore::remove(&name);
+                        return;
+                    }
                 };
 
-                // update config
-                config.service_states.remove(&name);
-                ServicesConfiguration::update_config(config.clone())
-                    .expect("Failed to update config");
+                // services stopped with `sproc kill` have already run their `post_stop` hooks
+                if matches!(StateStore::get(&name), Some(s) if s.state != ServiceState::Stopping) {
+                    match service.resolve(&config.vars) {
+                        Ok(s) => {
+                            let name = name.clone();
+                            let _ = tokio::task::spawn_blocking(move || {
+                                hooks::run_or_warn(&name, &s, Hook::PostStop)
+                            })
+                            .await;
+                        }
+                        Err(e) => println!("warn: {e}"),
+                    }
+                }
+
+                logs.event(&format!("exited ({status})"));
+                events::publish(&name, EventKind::exited(&status));
+
+                // restart right away when watched files changed (it isn't counted as a restart)
+                if restart.swap(false, Ordering::SeqCst) {
+                    if !Service::record(&name, ServiceState::Restarting, restarts, status.code()) {
+                        break;
+                    }
+
+                    events::publish(&name, EventKind::Restarting { restarts, delay: 0 });
+
+                    child = match Service::start_blocking(&name, restarts).await {
+                        Ok(c) => c,
+                        Err(e) => {
+                            println!("warn: {e}");
+                            logs.event(&format!("failed to restart: {e}"));
+                            Service::record(&name, ServiceState::Failed, restarts, status.code());
+                            break;
+                        }
+                    };
+
+                    continue;
+                }
 
-                // ...
-                if service.restart == false {
+                if !service.restart.should_restart(status.success()) {
                     // no need to loop again if we aren't supposed to restart the service
+                    Service::record(&name, ServiceState::Stopped, restarts, status.code());
+                    break;
+                }
+
+                // check for crash loop
+                let window = Duration::from_secs(service.restart_window);
+
+                if started.elapsed() >= window {
+                    // the service ran for a while, so it isn't crashing repeatedly
+                    delay_exponent = 0;
+                }
+
+                history.retain(|t| t.elapsed() < window);
+
+                if history.len() >= service.max_restarts as usize {
+                    if Service::record(&name, ServiceState::Failed, restarts, status.code()) {
+                        println!(
+                            "warn: service \"{}\" restarted {} times in {}s, marking as failed",
+                            name,
+                            history.len(),
+                            service.restart_window
+                        );
+
+                        logs.event(&format!(
+                            "failed (restarted {} times in {}s)",
+                            history.len(),
+                            service.restart_window
+                        ));
+                    }
+
+                    break;
+                }
+
+                history.push_back(Instant::now());
+
+                // wait before restarting
+                let delay = service
+                    .restart_delay
+                    .saturating_mul(2_u64.saturating_pow(delay_exponent))
+                    .min(service.restart_delay_max);
+
+                delay_exponent += 1;
+                restarts += 1;
+
+                if !Service::record(&name, ServiceState::Restarting, restarts, status.code()) {
                     break;
                 }
 
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+
+                // make sure the service wasn't stopped while we were waiting
+                match StateStore::get(&name) {
+                    Some(s) if s.state == ServiceState::Restarting => (),
+                    _ => break,
+                }
+
                 // begin restart
-                println!("info: auto-restarting service \"{}\"", name);
-                continue; // service will be run again
+                println!(
+                    "info: auto-restarting service \"{}\" (after {}ms)",
+                    name, delay
+                );
+
+                logs.event(&format!("restarting (after {delay}ms, restart {restarts})"));
+                events::publish(&name, EventKind::Restarting { restarts, delay });
+
+                child = match Service::start_blocking(&name, restarts).await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        println!("warn: {e}");
+                        logs.event(&format!("failed to restart: {e}"));
+                        Service::record(&name, ServiceState::Failed, restarts, status.code());
+                        break;
+                    }
+                };
             }
         });
+    }
+
+    /// Start a service (after its dependencies) and observe it in a new task
+    pub async fn spawn(name: String) -> Result<()> {
+        let config = ServicesConfiguration::get_config();
+        let states = StateStore::read();
+
+        // start dependencies first, in order
+        // every service is started before its task is created so dependents never start early
+        for service in config.start_order(std::slice::from_ref(&name))? {
+            if let Some(s) = states.get(&service) {
+                // dependencies that are already running don't need to be started again
+                if s.state.is_active() && (service != name) {
+                    continue;
+                }
+            }
+
+            let definition = match config.service(&service) {
+                Some(s) => s,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Service does not exist. ({service})"),
+                    ))
+                }
+            };
+
+            // timers are run once instead of being supervised (in a blocking task, like every
+            // start, since hooks can take a while)
+            if definition.r#type == ServiceType::Timer {
+                let definition = definition.resolve(&config.vars)?;
+                tokio::task::spawn_blocking(move || timers::fire(&service, &definition)).await??;
+                continue;
+            }
+
+            let child = Service::start_blocking(&service, 0).await?;
+            Service::supervise(service, child);
+        }
 
         // return
         Ok(())
@@ -356,14 +1183,8 @@ impl Service {
         fs::write(&build_file, self.metadata.build.join("\n"))?;
 
         // run build file
-        let command = format!("bash {build_file}");
-        let command_split: Vec<&str> = command.split(" ").collect();
-        let mut cmd = Command::new(command_split.get(0).unwrap());
-
-        for arg in command_split.iter().skip(1) {
-            cmd.arg(arg);
-        }
-
+        let mut cmd = Command::new("bash");
+        cmd.arg(&build_file);
         cmd.current_dir(&dir);
 
         // capture out
@@ -390,6 +1211,18 @@ impl Service {
 pub enum ServiceState {
     Running,
     Stopped,
+    /// The service is being stopped with `sproc kill`
+    Stopping,
+    /// The service exited and is waiting to be restarted
+    Restarting,
+    /// The service restarted too often (or couldn't be restarted) and was given up on
+    Failed,
+    /// The service is running, but its health check hasn't passed yet
+    Starting,
+    /// The service is running and its health check is passing
+    Healthy,
+    /// The service is running, but its health check is failing
+    Unhealthy,
 }
 
 impl Default for ServiceState {
@@ -398,15 +1231,145 @@ impl Default for ServiceState {
     }
 }
 
+impl ServiceState {
+    /// If the service has a running process in this state
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            ServiceState::Running
+                | ServiceState::Stopping
+                | ServiceState::Starting
+                | ServiceState::Healthy
+                | ServiceState::Unhealthy
+        )
+    }
+}
+
+/// The recorded state of a [`Service`]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ServiceStatus {
+    /// Current state
+    pub state: ServiceState,
+    /// The PID of the service process (`0` when not running)
+    #[serde(default)]
+    pub pid: u32,
+    /// The number of times the service was restarted automatically
+    #[serde(default)]
+    pub restarts: u32,
+    /// The exit code of the last service process (`None` if it was killed by a signal)
+    pub exit_code: Option<i32>,
+    /// The start time of the service process (seconds since epoch)
+    #[serde(default)]
+    pub started_at: u64,
+    /// The executable of the service process
+    #[serde(default)]
+    pub executable: String,
+    /// The last time a timer was run (seconds since epoch, `0` if it never ran)
+    #[serde(default)]
+    pub last_run: u64,
+}
+
+impl ServiceStatus {
+    /// Status of a service that was just started
+    pub fn running(pid: u32) -> Self {
+        let mut status = Self {
+            state: ServiceState::Running,
+            pid,
+            restarts: 0,
+            exit_code: None,
+            started_at: 0,
+            executable: String::new(),
+            last_run: 0,
+        };
+
+        // remember which process this is, so we don't mistake another process for it once
+        // the PID is reused
+        let mut sys = System::new();
+
+        if sys.refresh_process(Pid::from(pid as usize)) {
+            if let Some(process) = sys.process(Pid::from(pid as usize)) {
+                status.started_at = process.start_time();
+                status.executable = match process.exe() {
+                    Some(exe) => exe.to_string_lossy().to_string(),
+                    None => String::new(),
+                };
+            }
+        }
+
+        status
+    }
+
+    /// Get the service process, only if the process with the recorded PID is still the one that
+    /// was started (same start time and executable)
+    pub fn process<'a>(&self, sys: &'a System) -> Option<&'a Process> {
+        let process = sys.process(Pid::from(self.pid as usize))?;
+
+        if (self.started_at != 0) && (process.start_time() != self.started_at) {
+            return None;
+        }
+
+        if let Some(exe) = process.exe() {
+            if !self.executable.is_empty() && (exe.to_string_lossy() != self.executable) {
+                return None;
+            }
+        }
+
+        Some(process)
+    }
+}
+
 /// General information about a [`ServiceState`]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ServiceInfo {
     pub name: String,
-    pub pid: u32,
-    pub memory: u64,
-    pub cpu: f32,
-    pub status: String,
-    pub running_for_seconds: u64,
+    pub state: ServiceState,
+    pub restarts: u32,
+    pub exit_code: Option<i32>,
+    pub pid: Option<u32>,
+    pub memory: Option<u64>,
+    pub cpu: Option<f32>,
+    pub status: Option<String>,
+    pub running_for_seconds: Option<u64>,
+    /// The number of replicas the usage (and restarts) were added up from
+    pub replicas: Option<u32>,
+    /// Configured resource limits
+    pub limits: Option<ResourceLimits>,
+}
+
+impl ServiceInfo {
+    /// Add the info of another replica of the same service to this info
+    ///
+    /// The name of this info is replaced by the name of the service, while its state and PID
+    /// stay the ones of the first replica.
+    pub fn add_replica(&mut self, other: ServiceInfo) {
+        self.name = replica_of(&self.name).0.to_string();
+        self.replicas = Some(self.replicas.unwrap_or(1) + 1);
+        self.restarts += other.restarts;
+
+        if let Some(memory) = other.memory {
+            self.memory = Some(self.memory.unwrap_or(0) + memory);
+        }
+
+        if let Some(cpu) = other.cpu {
+            self.cpu = Some(self.cpu.unwrap_or(0.0) + cpu);
+        }
+    }
+}
+
+/// A summary of a configured [`Service`] (`sproc status`)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServiceSummary {
+    pub name: String,
+    pub r#type: ServiceType,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    /// Seconds the service process has been running for
+    pub uptime: Option<u64>,
+    pub restarts: u32,
+    /// CPU usage (percent)
+    pub cpu: Option<f32>,
+    /// Memory usage (bytes)
+    pub memory: Option<u64>,
 }
 
 /// Configuration for `sproc serve`'s registry
@@ -450,6 +1413,12 @@ pub struct ServerConfiguration {
     /// Configuration for the registry
     #[serde(default)]
     pub registry: RegistryConfiguration,
+    /// Configuration for metrics sampling
+    #[serde(default)]
+    pub metrics: MetricsConfiguration,
+    /// The path of the Unix socket the server also listens on (see
+    /// [`ServerConfiguration::socket_path`])
+    pub socket: Option<String>,
 }
 
 impl Default for ServerConfiguration {
@@ -458,6 +1427,26 @@ impl Default for ServerConfiguration {
             port: 6374,
             key: String::new(),
             registry: RegistryConfiguration::default(),
+            metrics: MetricsConfiguration::default(),
+            socket: None,
+        }
+    }
+}
+
+impl ServerConfiguration {
+    /// Get the path of the server's Unix socket
+    ///
+    /// `$XDG_RUNTIME_DIR/sproc.sock` by default, or `$HOME/.config/xsu-apps/sproc/sproc.sock`
+    /// if `$XDG_RUNTIME_DIR` isn't set.
+    pub fn socket_path(&self) -> PathBuf {
+        if let Some(ref socket) = self.socket {
+            return PathBuf::from(socket);
+        }
+
+        match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("sproc.sock"),
+            _ => PathBuf::from(env::var("HOME").unwrap_or_default())
+                .join(".config/xsu-apps/sproc/sproc.sock"),
         }
     }
 }
@@ -468,16 +1457,22 @@ pub struct ServicesConfiguration {
     /// The source file location
     #[serde(default)]
     pub source: String,
-    /// Inherited service definition files
+    /// Inherited service definition files (paths or glob patterns), merged on top of this file
     pub inherit: Option<Vec<String>>,
     /// Server configuration (`sproc serve`)
     #[serde(default)]
     pub server: ServerConfiguration,
+    /// Variables that can be used in services (`${VAR}`)
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
     /// Service definitions
     pub services: HashMap<String, Service>,
-    /// Service states
+    /// Named groups of services (`--group`)
     #[serde(default)]
-    pub service_states: ServiceStates,
+    pub groups: HashMap<String, Vec<String>>,
+    /// Configuration merged on top of everything else on the host with the same name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub overlay: HashMap<String, toml::Table>,
 }
 
 impl Default for ServicesConfiguration {
@@ -487,30 +1482,57 @@ impl Default for ServicesConfiguration {
             inherit: None,
             services: HashMap::new(),
             server: ServerConfiguration::default(),
-            service_states: HashMap::new(),
+            vars: HashMap::new(),
+            groups: HashMap::new(),
+            overlay: HashMap::new(),
         }
     }
 }
 
 impl ServicesConfiguration {
-    /// Read configuration file into [`ServicesConfiguration`]
-    pub fn read(contents: String) -> Self {
-        let mut res = toml::from_str::<Self>(&contents).unwrap();
-
-        // handle inherits
-        if let Some(ref inherit) = res.inherit {
-            for path in inherit {
-                if let Ok(c) = fs::read_to_string(path) {
-                    for service in toml::from_str::<Self>(&c).unwrap().services {
-                        // push service to main service stack
-                        res.services.insert(service.0, service.1);
+    /// Read configuration file into [`ServicesConfiguration`] (with every layer applied)
+    ///
+    /// Inherited files that can't be loaded are skipped, and so are services that are invalid
+    /// once every layer is applied (they are reported by `sproc check`).
+    pub fn read(contents: String) -> Result<Self> {
+        let mut layers = Layers::load(&contents, "")?;
+
+        if let Some(toml::Value::Table(services)) = layers.value.get_mut("services") {
+            services.retain(
+                |name, service| match service.clone().try_into::<Service>() {
+                    Ok(_) => true,
+                    Err(e) => {
+                        println!(
+                            "warn: skipping invalid service \"{name}\": {}",
+                            e.message().trim()
+                        );
+                        false
                     }
-                }
-            }
+                },
+            );
         }
 
-        // return
-        res
+        Self::from_layers(&layers)
+    }
+
+    /// Get a [`ServicesConfiguration`] from merged [`Layers`]
+    pub fn from_layers(layers: &Layers) -> Result<Self> {
+        match toml::Value::Table(layers.value.clone()).try_into() {
+            Ok(c) => Ok(c),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+
+    /// The path of the pinned configuration file
+    pub fn pinned_path() -> String {
+        let home = env::var("HOME").expect("failed to read $HOME");
+        format!("{home}/.config/xsu-apps/sproc/services.toml")
+    }
+
+    /// Get the layers of the pinned configuration file
+    pub fn get_layers() -> Result<Layers> {
+        let contents = fs::read(Self::pinned_path())?;
+        Layers::load(&contents, "")
     }
 
     /// Pull configuration file
@@ -531,7 +1553,13 @@ impl ServicesConfiguration {
         }
 
         match fs::read(format!("{home}/.config/xsu-apps/sproc/services.toml")) {
-            Ok(c) => ServicesConfiguration::read(c),
+            Ok(c) => match ServicesConfiguration::read(c) {
+                Ok(c) => c,
+                Err(e) => {
+                    println!("warn: failed to read pinned configuration: {e}");
+                    Self::default()
+                }
+            },
             Err(_) => Self::default(),
         }
     }
@@ -546,11 +1574,457 @@ impl ServicesConfiguration {
         )
     }
 
-    /// Merge services from other [`ServicesConfiguration`]
-    pub fn merge_config(&mut self, other: Self) -> () {
-        for service in other.services {
-            // push service to main service stack
-            self.services.insert(service.0, service.1);
+    /// Get a [`ServiceSummary`] of every service, sorted by name
+    pub fn status(&self, service_states: &ServiceStates) -> Vec<ServiceSummary> {
+        // cpu usage is measured between two refreshes
+        let mut sys = System::new();
+        sys.refresh_processes();
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        sys.refresh_processes();
+
+        self.names_with_instances(service_states)
+            .into_iter()
+            .map(|name| {
+                let state = service_states.get(&name).cloned().unwrap_or_default();
+                let process = match state.state.is_active() {
+                    true => state.process(&sys),
+                    false => None,
+                };
+
+                ServiceSummary {
+                    r#type: self.service(&name).map(|s| s.r#type).unwrap_or_default(),
+                    state: state.state.clone(),
+                    pid: process.map(|_| state.pid),
+                    uptime: process.map(|p| p.run_time()),
+                    restarts: state.restarts,
+                    cpu: process.map(|p| p.cpu_usage()),
+                    memory: process.map(|p| p.memory()),
+                    name,
+                }
+            })
+            .collect()
+    }
+
+    /// Get a service by name
+    ///
+    /// Instances of template services (`name@instance`) are created from their template
+    /// (`name@`), see [`Service::instantiate`]. Replicas (`name#index`) are created from their
+    /// service, see [`Service::replica`].
+    pub fn service(&self, name: &str) -> Option<Service> {
+        let (name, index) = replica_of(name);
+
+        if index > 0 {
+            return self.service(name).map(|s| s.replica(index));
+        }
+
+        // the first replica only gets its index (and port) if the service is replicated
+        let first = |service: Service| match (service.replicas > 1) || service.base_port.is_some() {
+            true => service.replica(0),
+            false => service,
+        };
+
+        if let Some(service) = self.services.get(name) {
+            if is_template(name) {
+                return None;
+            }
+
+            return Some(first(service.clone()));
+        }
+
+        let (template, instance) = name.split_once('@')?;
+
+        if !is_instance_name(instance) {
+            return None;
+        }
+
+        self.services
+            .get(&format!("{template}@"))
+            .map(|s| first(s.instantiate(instance)))
+    }
+
+    /// Get the names of all services (excluding templates), sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .services
+            .keys()
+            .filter(|n| !is_template(n))
+            .cloned()
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Get the names of all services with `autostart` (and their replicas), sorted
+    pub fn autostart(&self) -> Vec<String> {
+        let names: Vec<String> = self
+            .names()
+            .into_iter()
+            .filter(|n| {
+                let service = &self.services[n];
+                service.autostart && (service.r#type == ServiceType::Service)
+            })
+            .collect();
+
+        self.with_replicas(&names, &ServiceStates::new())
+    }
+
+    /// Get the names of all services, and every instance of a template service (or replica) that
+    /// has a state, sorted
+    pub fn names_with_instances(&self, service_states: &ServiceStates) -> Vec<String> {
+        let mut names = self.names();
+
+        for name in service_states.keys() {
+            if !self.services.contains_key(name) && self.service(name).is_some() {
+                names.push(name.to_owned());
+            }
+        }
+
+        names.sort();
+        names
+    }
+
+    /// Get the names of the services selected by name, tag or group, sorted
+    ///
+    /// Services are selected if they match any of the given names, tags or groups. Instances of
+    /// template services are only selected by tag if they have a state.
+    pub fn select(
+        &self,
+        names: &[String],
+        tags: &[String],
+        groups: &[String],
+    ) -> Result<Vec<String>> {
+        let mut selected = Vec::new();
+
+        for name in names {
+            if self.service(name).is_none() {
+                let invalid_instance = name.split_once('@').is_some_and(|(template, instance)| {
+                    self.services.contains_key(&format!("{template}@"))
+                        && !is_instance_name(instance)
+                });
+
+                let message = if is_template(name) {
+                    format!(
+                        "Service is a template, please provide an instance name. ({name}instance)"
+                    )
+                } else if invalid_instance {
+                    format!("Instance names can only contain letters, digits, \".\", \"_\" and \"-\". ({name})")
+                } else {
+                    format!("Service does not exist. ({name})")
+                };
+
+                return Err(Error::new(ErrorKind::NotFound, message));
+            }
+
+            selected.push(name.to_owned());
+        }
+
+        // instances of templates are selected by tag once they have a state
+        let all = match tags.is_empty() {
+            true => Vec::new(),
+            false => self.names_with_instances(&StateStore::read()),
+        };
+
+        for tag in tags {
+            let tagged: Vec<String> = all
+                .iter()
+                .filter(|n| self.service(n).is_some_and(|s| s.tags.contains(tag)))
+                .cloned()
+                .collect();
+
+            if tagged.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("No services have this tag. ({tag})"),
+                ));
+            }
+
+            selected.extend(tagged);
+        }
+
+        for group in groups {
+            let members = match self.groups.get(group) {
+                Some(m) => m,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Group does not exist. ({group})"),
+                    ))
+                }
+            };
+
+            for name in members {
+                if self.service(name).is_none() {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Service in group \"{group}\" does not exist. ({name})"),
+                    ));
+                }
+            }
+
+            selected.extend(members.iter().cloned());
+        }
+
+        let mut selected = self.with_replicas(&selected, &StateStore::read());
+        selected.sort();
+        selected.dedup();
+        Ok(selected)
+    }
+
+    /// Get the given services with every one of their replicas (the configured number of
+    /// replicas, and every other replica that is running)
+    ///
+    /// Names of replicas (`name#index`) are kept as-is.
+    pub fn with_replicas(&self, names: &[String], service_states: &ServiceStates) -> Vec<String> {
+        let mut res = Vec::new();
+
+        for name in names {
+            let service = match self.service(name) {
+                Some(s) if (replica_of(name).1 == 0) && (s.r#type == ServiceType::Service) => s,
+                _ => {
+                    res.push(name.to_owned());
+                    continue;
+                }
+            };
+
+            res.extend(replica_names(name, service.replicas.max(1)));
+
+            for (state_name, state) in service_states {
+                let (base, index) = replica_of(state_name);
+
+                if (base == name) && (index >= service.replicas) && state.state.is_active() {
+                    res.push(state_name.to_owned());
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Get every tag used by a service, sorted
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self
+            .names()
+            .iter()
+            .flat_map(|n| self.services[n].tags.iter().cloned())
+            .collect();
+
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// Get the order the given services (and their dependencies) must be started in
+    ///
+    /// Every service is placed after the services it depends on. Cyclic or missing dependencies
+    /// are rejected.
+    pub fn start_order(&self, names: &[String]) -> Result<Vec<String>> {
+        let mut order = Vec::new();
+
+        for name in names {
+            self.visit_dependencies(name, &mut Vec::new(), &mut order, true)?;
+        }
+
+        Ok(order)
+    }
+
+    /// Get the order the given services must be stopped in (dependents before dependencies)
+    ///
+    /// Unlike [`ServicesConfiguration::start_order`], broken dependencies are ignored so services
+    /// can always be stopped.
+    pub fn stop_order(&self, names: &[String]) -> Vec<String> {
+        let mut order = Vec::new();
+
+        for name in names {
+            // errors are never returned when not strict
+            let _ = self.visit_dependencies(name, &mut Vec::new(), &mut order, false);
+        }
+
+        order
+            .into_iter()
+            .rev()
+            .filter(|n| names.contains(n))
+            .collect()
+    }
+
+    /// Depth-first walk used by [`ServicesConfiguration::start_order`]
+    fn visit_dependencies(
+        &self,
+        name: &String,
+        visiting: &mut Vec<String>,
+        order: &mut Vec<String>,
+        strict: bool,
+    ) -> Result<()> {
+        if order.contains(name) {
+            return Ok(());
+        }
+
+        // check for cycles
+        if let Some(i) = visiting.iter().position(|n| n == name) {
+            if !strict {
+                return Ok(());
+            }
+
+            let mut cycle = visiting[i..].to_vec();
+            cycle.push(name.to_owned());
+
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Dependency cycle detected. ({})", cycle.join(" -> ")),
+            ));
+        }
+
+        let service = match self.service(name) {
+            Some(s) => s,
+            None => {
+                if !strict {
+                    return Ok(());
+                }
+
+                return Err(match visiting.last() {
+                    Some(parent) => Error::new(
+                        ErrorKind::NotFound,
+                        format!("Service depends on a service that does not exist. ({parent} -> {name})"),
+                    ),
+                    None => Error::new(
+                        ErrorKind::NotFound,
+                        format!("Service does not exist. ({name})"),
+                    ),
+                });
+            }
+        };
+
+        // visit dependencies
+        visiting.push(name.to_owned());
+
+        for dependency in &service.depends_on {
+            self.visit_dependencies(dependency, visiting, order, strict)?;
+        }
+
+        visiting.pop();
+        order.push(name.to_owned());
+
+        Ok(())
+    }
+
+    /// Get a copy of the configuration with every service resolved and expanded, to be shown
+    /// (see [`Service::resolve`] and [`Service::expanded`])
+    pub fn resolve(&self) -> Result<Self> {
+        let mut res = self.clone();
+
+        for (name, service) in res.services.iter_mut() {
+            *service = match service.resolve(&self.vars).and_then(|s| s.expanded()) {
+                Ok(s) => s,
+                Err(e) => return Err(Error::new(e.kind(), format!("{e} (service \"{name}\")"))),
+            };
+        }
+
+        Ok(res)
+    }
+
+    /// Edit an unmerged configuration file (the source file, or the pinned file), keeping
+    /// everything that isn't changed as it is (layers are only ever applied when reading)
+    pub fn edit_file<T>(path: &str, f: impl FnOnce(&mut DocumentMut) -> Result<T>) -> Result<T> {
+        let mut document: DocumentMut = match std::fs::read_to_string(path)?.parse() {
+            Ok(d) => d,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, format!("{e} ({path})"))),
+        };
+
+        let res = f(&mut document)?;
+        std::fs::write(path, document.to_string())?;
+
+        Ok(res)
+    }
+
+    /// Merge the services, vars and groups of another (merged) configuration into an unmerged
+    /// configuration file
+    pub fn merge_file(path: &str, other: &toml::Table) -> Result<()> {
+        if let Some(toml::Value::Table(services)) = other.get("services") {
+            check_service_names(services.keys())?;
+        }
+
+        Self::edit_file(path, |document| {
+            for key in ["services", "vars", "groups"] {
+                if let Some(toml::Value::Table(values)) = other.get(key) {
+                    let table = table_mut(document, key)?;
+
+                    for (name, value) in values {
+                        table.insert(name, to_item(value));
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Remove services from an unmerged configuration file, returning the names of the
+    /// services that weren't set in it
+    pub fn remove_from_file(path: &str, names: &[String]) -> Result<Vec<String>> {
+        Self::edit_file(path, |document| {
+            let services = table_mut(document, "services")?;
+
+            Ok(names
+                .iter()
+                .filter(|name| services.remove(name).is_none())
+                .cloned()
+                .collect())
+        })
+    }
+}
+
+/// Get a top-level table of a document, creating it if it doesn't exist
+fn table_mut<'a>(document: &'a mut DocumentMut, key: &str) -> Result<&'a mut toml_edit::Table> {
+    let mut table = toml_edit::Table::new();
+    table.set_implicit(true);
+
+    match document
+        .entry(key)
+        .or_insert(Item::Table(table))
+        .as_table_mut()
+    {
+        Some(t) => Ok(t),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("\"{key}\" must be a table."),
+        )),
+    }
+}
+
+/// Convert a TOML value into an item that can be inserted into a document (tables are added as
+/// sections of their own)
+fn to_item(value: &toml::Value) -> Item {
+    match value {
+        toml::Value::Table(table) => {
+            let mut item = toml_edit::Table::new();
+
+            for (key, value) in table {
+                item.insert(key, to_item(value));
+            }
+
+            Item::Table(item)
+        }
+        value => Item::Value(to_value(value)),
+    }
+}
+
+/// Convert a TOML value into an inline value
+fn to_value(value: &toml::Value) -> toml_edit::Value {
+    match value {
+        toml::Value::String(s) => s.as_str().into(),
+        toml::Value::Integer(i) => (*i).into(),
+        toml::Value::Float(f) => (*f).into(),
+        toml::Value::Boolean(b) => (*b).into(),
+        toml::Value::Datetime(d) => (*d).into(),
+        toml::Value::Array(a) => toml_edit::Value::Array(a.iter().map(to_value).collect()),
+        toml::Value::Table(t) => {
+            let mut table = toml_edit::InlineTable::new();
+
+            for (key, value) in t {
+                table.insert(key, to_value(value));
+            }
+
+            toml_edit::Value::InlineTable(table)
         }
     }
 }
@@ -0,0 +1,190 @@
+//! Sproc command output (`--output json|toml|table`)
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The format command output is printed in
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// JSON, for scripts
+    Json,
+    /// TOML, the same format as the configuration
+    Toml,
+    /// Human-readable text and tables
+    #[default]
+    Table,
+}
+
+impl OutputFormat {
+    /// If this format is meant to be read by other programs
+    pub fn is_structured(&self) -> bool {
+        *self != OutputFormat::Table
+    }
+}
+
+/// The result of a command that doesn't print any other data
+#[derive(Serialize)]
+pub struct Message {
+    pub ok: bool,
+    pub message: String,
+}
+
+/// If a command printed its data (so the final [`Message`] isn't printed as structured data)
+static PRINTED: AtomicBool = AtomicBool::new(false);
+
+/// Render a value in the given format
+///
+/// Lists are placed under `key` in TOML (documents must be tables). Tables have a column for
+/// every field of the listed values (or a row for every field of a single value), nested values
+/// are shown as JSON.
+pub fn render<T: Serialize>(format: OutputFormat, key: &str, value: &T) -> Result<String> {
+    let json = match serde_json::to_value(value) {
+        Ok(v) => v,
+        Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+    };
+
+    match format {
+        OutputFormat::Json => match serde_json::to_string_pretty(&json) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        },
+        OutputFormat::Toml => {
+            let res = if json.is_array() {
+                toml::to_string_pretty(&HashMap::from([(key, value)]))
+            } else {
+                toml::to_string_pretty(value)
+            };
+
+            match res {
+                Ok(s) => Ok(s.trim_end().to_string()),
+                Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+            }
+        }
+        OutputFormat::Table => Ok(table(&json)),
+    }
+}
+
+/// Print a value in the given format (see [`render`])
+pub fn print<T: Serialize>(format: OutputFormat, key: &str, value: &T) -> Result<()> {
+    println!("{}", render(format, key, value)?);
+    PRINTED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Print a line that is only meant for people (sent to stderr when the output is structured, so
+/// it doesn't end up in the data)
+pub fn log(format: OutputFormat, line: &str) {
+    if format.is_structured() {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+/// Print the final result of a command
+///
+/// Returns `false` if the command failed.
+pub fn finish(format: OutputFormat, result: Result<&str>) -> bool {
+    let (ok, message) = match result {
+        Ok(m) => (true, m.to_string()),
+        Err(e) => (false, e.to_string()),
+    };
+
+    if !format.is_structured() {
+        if ok {
+            xsu_util::process::yes(&message);
+        } else {
+            xsu_util::process::no(&message);
+        }
+
+        return ok;
+    }
+
+    // the data printed by the command is the only thing on stdout
+    if PRINTED.load(Ordering::SeqCst) {
+        if !ok {
+            eprintln!("{message}");
+        }
+
+        return ok;
+    }
+
+    match render(format, "message", &Message { ok, message }) {
+        Ok(s) => println!("{s}"),
+        Err(e) => eprintln!("{e}"),
+    }
+
+    ok
+}
+
+/// Render a JSON value as a text table
+fn table(value: &Value) -> String {
+    let (header, rows): (Vec<String>, Vec<Vec<String>>) = match value {
+        Value::Array(items) if items.iter().all(|i| i.is_object()) => {
+            // columns are taken from the first row (every row has the same fields)
+            let header: Vec<String> = match items.first() {
+                Some(Value::Object(o)) => o.keys().cloned().collect(),
+                _ => return String::new(),
+            };
+
+            let rows = items
+                .iter()
+                .map(|i| header.iter().map(|k| cell(&i[k])).collect())
+                .collect();
+
+            (header, rows)
+        }
+        Value::Array(items) => {
+            return items.iter().map(cell).collect::<Vec<String>>().join("\n");
+        }
+        Value::Object(o) => (
+            vec!["key".to_string(), "value".to_string()],
+            o.iter().map(|(k, v)| vec![k.to_owned(), cell(v)]).collect(),
+        ),
+        value => return cell(value),
+    };
+
+    // pad every column to its widest cell
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+
+    for row in &rows {
+        for (i, c) in row.iter().enumerate() {
+            widths[i] = widths[i].max(c.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<String>| -> String {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{c:<width$}", width = widths[i]))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut out = vec![line(header.iter().map(|h| h.to_uppercase()).collect())];
+
+    for row in rows {
+        out.push(line(row));
+    }
+
+    out.join("\n")
+}
+
+/// Render a single table cell
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.to_owned(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        value => value.to_string(),
+    }
+}
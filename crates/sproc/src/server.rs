@@ -1,15 +1,24 @@
 //! Sproc HTTP endpoints
 use askama_axum::Template;
-use axum::extract::Path;
-use axum::response::IntoResponse;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{self, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{delete, get, post};
 use axum::{Form, Router};
 use axum::{extract::State, response::Html, Json};
+use std::collections::HashMap;
 use std::process::Command;
 
+use crate::events::{self, EventKind};
+use crate::logs::{self, LogStream, ServiceLogs};
+use crate::metrics;
+use crate::state::{SavedServices, StateStore};
+use crate::timers;
 use crate::model::{
-    Registry, RegistryConfiguration, RegistryDeleteRequestBody, RegistryPushRequestBody, Service,
-    ServicesConfiguration as ServConf,
+    self, Registry, RegistryConfiguration, RegistryDeleteRequestBody, RegistryPushRequestBody,
+    Service, ServiceState, ServicesConfiguration as ServConf,
 };
 use xsu_authman::{Database as AuthDatabase, model::AuthError};
 use serde::{Deserialize, Serialize};
@@ -34,6 +43,15 @@ pub struct BasicServiceRequestBody {
     pub key: String,
 }
 
+/// Request body for operations on a group of services
+#[derive(Serialize, Deserialize)]
+pub struct GroupRequestBody {
+    /// The name of the group
+    pub group: String,
+    /// Auth key
+    pub key: String,
+}
+
 /// Basic request body for operations on a specific service
 #[derive(Serialize, Deserialize)]
 pub struct InstallRequestBody {
@@ -45,6 +63,51 @@ pub struct InstallRequestBody {
     pub key: String,
 }
 
+/// Request body for reading service logs
+#[derive(Serialize, Deserialize)]
+pub struct LogsRequestBody {
+    /// The name of the service
+    pub service: String,
+    /// Auth key
+    pub key: String,
+    /// The number of lines to return
+    #[serde(default = "lines_default")]
+    pub lines: usize,
+    /// If stderr should be read instead of stdout
+    #[serde(default)]
+    pub stderr: bool,
+    /// If the lifecycle log should be read instead of stdout
+    #[serde(default)]
+    pub lifecycle: bool,
+}
+
+fn lines_default() -> usize {
+    50
+}
+
+/// Query for streaming service events
+#[derive(Serialize, Deserialize)]
+pub struct EventsQuery {
+    /// Auth key
+    pub key: String,
+    /// If new events should be streamed as they happen (only the recent events are sent
+    /// otherwise)
+    #[serde(default = "follow_default")]
+    pub follow: bool,
+}
+
+fn follow_default() -> bool {
+    true
+}
+
+/// Query for the Prometheus metrics
+#[derive(Serialize, Deserialize)]
+pub struct MetricsQuery {
+    /// Auth key (it can also be sent as a bearer token)
+    #[serde(default)]
+    pub key: String,
+}
+
 /// Default 404 response
 /// { "ok": false, "data": (http status) }
 pub async fn not_found() -> impl IntoResponse {
@@ -54,6 +117,14 @@ pub async fn not_found() -> impl IntoResponse {
     })
 }
 
+/// Check if the server is running (GET /ping)
+pub async fn ping_request() -> impl IntoResponse {
+    Json(APIReturn::<u16> {
+        ok: true,
+        data: 200,
+    })
+}
+
 /// Start and observe a service (POST /start)
 pub async fn observe_request(
     State(config): State<ServConf>, // inital config from server start
@@ -61,24 +132,24 @@ pub async fn observe_request(
 ) -> impl IntoResponse {
     // check key
     if body.key != config.server.key {
-        return Json(APIReturn::<u16> {
+        return Json(APIReturn::<String> {
             ok: false,
-            data: 401,
+            data: String::new(),
         });
     }
 
     // start
-    if let Err(_) = Service::spawn(body.service.clone()).await {
-        return Json(APIReturn::<u16> {
+    if let Err(e) = Service::spawn(body.service.clone()).await {
+        return Json(APIReturn::<String> {
             ok: false,
-            data: 400,
+            data: e.to_string(),
         });
     };
 
     // return
-    Json(APIReturn::<u16> {
+    Json(APIReturn::<String> {
         ok: true,
-        data: 200,
+        data: "Service started.".to_string(),
     })
 }
 
@@ -89,32 +160,229 @@ pub async fn kill_request(
 ) -> impl IntoResponse {
     // check key
     if body.key != config.server.key {
-        return Json(APIReturn::<u16> {
+        return Json(APIReturn::<String> {
             ok: false,
-            data: 401,
+            data: String::new(),
         });
     }
 
     // get updated config
-    let mut config = ServConf::get_config();
+    let config = ServConf::get_config();
 
     // kill
-    // TODO: try to clone less
-    if let Err(_) = Service::kill(body.service.clone(), config.clone()) {
-        return Json(APIReturn::<u16> {
+    if let Err(e) = Service::kill_blocking(body.service.clone(), config).await {
+        return Json(APIReturn::<String> {
+            ok: false,
+            data: e.to_string(),
+        });
+    };
+
+    // return
+    Json(APIReturn::<String> {
+        ok: true,
+        data: "Service stopped.".to_string(),
+    })
+}
+
+/// Start and observe every service in a group (POST /group/start)
+pub async fn group_start_request(
+    State(config): State<ServConf>, // inital config from server start
+    Json(body): Json<GroupRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if body.key != config.server.key {
+        return Json(APIReturn::<String> {
             ok: false,
-            data: 400,
+            data: String::new(),
         });
+    }
+
+    // get updated config
+    let config = ServConf::get_config();
+
+    let names = match config.select(&[], &[], std::slice::from_ref(&body.group)) {
+        Ok(n) => n,
+        Err(e) => {
+            return Json(APIReturn::<String> {
+                ok: false,
+                data: e.to_string(),
+            })
+        }
     };
 
-    // update config
-    config.service_states.remove(&body.service);
-    ServConf::update_config(config.clone()).unwrap();
+    // start
+    let states = StateStore::read();
+
+    for name in names {
+        // services that are already running are left alone
+        if states.get(&name).is_some_and(|s| s.state.is_active()) {
+            continue;
+        }
+
+        if let Err(e) = Service::spawn(name).await {
+            return Json(APIReturn::<String> {
+                ok: false,
+                data: e.to_string(),
+            });
+        };
+    }
 
     // return
-    Json(APIReturn::<u16> {
+    Json(APIReturn::<String> {
         ok: true,
-        data: 200,
+        data: "Group started.".to_string(),
+    })
+}
+
+/// Kill every running service in a group (POST /group/kill)
+pub async fn group_kill_request(
+    State(config): State<ServConf>, // inital config from server start
+    Json(body): Json<GroupRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if body.key != config.server.key {
+        return Json(APIReturn::<String> {
+            ok: false,
+            data: String::new(),
+        });
+    }
+
+    // get updated config
+    let config = ServConf::get_config();
+
+    let names = match config.select(&[], &[], std::slice::from_ref(&body.group)) {
+        Ok(n) => n,
+        Err(e) => {
+            return Json(APIReturn::<String> {
+                ok: false,
+                data: e.to_string(),
+            })
+        }
+    };
+
+    // kill (dependents before their dependencies)
+    let states = StateStore::read();
+
+    for name in config.stop_order(&names) {
+        if !states.get(&name).is_some_and(|s| s.state.is_active()) {
+            continue;
+        }
+
+        if let Err(e) = Service::kill_blocking(name, config.clone()).await {
+            return Json(APIReturn::<String> {
+                ok: false,
+                data: e.to_string(),
+            });
+        };
+    }
+
+    // return
+    Json(APIReturn::<String> {
+        ok: true,
+        data: "Group stopped.".to_string(),
+    })
+}
+
+/// Stream service events as Server-Sent Events, or over a WebSocket when upgraded
+/// (GET /events)
+///
+/// Every event is sent as JSON, starting with the recent events.
+pub async fn events_request(
+    State(config): State<ServConf>, // inital config from server start
+    Query(query): Query<EventsQuery>,
+    ws: Option<WebSocketUpgrade>,
+) -> Response {
+    // check key
+    if query.key != config.server.key {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(APIReturn::<String> {
+                ok: false,
+                data: String::new(),
+            }),
+        )
+            .into_response();
+    }
+
+    let subscription = events::subscribe(query.follow);
+
+    // websocket
+    if let Some(ws) = ws {
+        return ws
+            .on_upgrade(move |socket| events_socket(socket, subscription))
+            .into_response();
+    }
+
+    // sse
+    let stream = futures_util::stream::unfold(subscription, |mut subscription| async move {
+        let event = subscription.next().await?;
+        Some((sse::Event::default().json_data(&event), subscription))
+    });
+
+    Sse::new(stream)
+        .keep_alive(sse::KeepAlive::default())
+        .into_response()
+}
+
+/// Send events to a WebSocket until it is closed
+async fn events_socket(mut socket: WebSocket, mut subscription: events::Subscription) {
+    while let Some(event) = subscription.next().await {
+        let text = match serde_json::to_string(&event) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+
+        if socket.send(Message::Text(text)).await.is_err() {
+            // the socket was closed
+            return;
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Get the latest metrics of every running service in the Prometheus text format (GET /metrics)
+///
+/// The key is read from the `key` query parameter, or the `Authorization: Bearer` header.
+pub async fn metrics_request(
+    State(config): State<ServConf>, // inital config from server start
+    Query(query): Query<MetricsQuery>,
+    headers: HeaderMap,
+) -> Response {
+    // check key
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if (query.key != config.server.key) && (bearer != Some(config.server.key.as_str())) {
+        return (StatusCode::UNAUTHORIZED, String::new()).into_response();
+    }
+
+    (
+        [("Content-Type", "text/plain; version=0.0.4")],
+        metrics::prometheus(),
+    )
+        .into_response()
+}
+
+/// Get the recent metrics of every running service (POST /metrics/history)
+pub async fn metrics_history_request(
+    State(config): State<ServConf>, // inital config from server start
+    Json(body): Json<IndexBody>,
+) -> impl IntoResponse {
+    // check key
+    if body.key != config.server.key {
+        return Json(APIReturn {
+            ok: false,
+            data: HashMap::new(),
+        });
+    }
+
+    // return
+    Json(APIReturn {
+        ok: true,
+        data: metrics::get_history(),
     })
 }
 
@@ -131,14 +399,57 @@ pub async fn info_request(
         });
     }
 
+    // return
+    Json(APIReturn::<String> {
+        ok: true,
+        data: match Service::info(body.service.clone(), StateStore::read()) {
+            Ok(i) => toml::to_string_pretty(&i).unwrap(),
+            Err(e) => {
+                return Json(APIReturn::<String> {
+                    ok: false,
+                    data: e.to_string(),
+                })
+            }
+        },
+    })
+}
+
+/// Get the last lines of a service's logs (POST /logs)
+pub async fn logs_request(
+    State(config): State<ServConf>, // inital config from server start
+    Json(body): Json<LogsRequestBody>,
+) -> impl IntoResponse {
+    // check key
+    if body.key != config.server.key {
+        return Json(APIReturn::<String> {
+            ok: false,
+            data: String::new(),
+        });
+    }
+
     // get updated config
     let config = ServConf::get_config();
 
+    if config.service(&body.service).is_none() {
+        return Json(APIReturn::<String> {
+            ok: false,
+            data: format!("Service does not exist. ({})", body.service),
+        });
+    }
+
     // return
+    let stream = if body.lifecycle {
+        LogStream::Lifecycle
+    } else if body.stderr {
+        LogStream::Stderr
+    } else {
+        LogStream::Stdout
+    };
+
     Json(APIReturn::<String> {
         ok: true,
-        data: match Service::info(body.service.clone(), config.service_states) {
-            Ok(i) => i,
+        data: match ServiceLogs::new(&body.service).tail(stream, body.lines) {
+            Ok(l) => l,
             Err(e) => {
                 return Json(APIReturn::<String> {
                     ok: false,
@@ -166,8 +477,15 @@ pub async fn install_request(
     let mut cmd = Command::new("sproc");
     cmd.arg("install");
     cmd.arg(body.registry.replace("https://", "").replace("http://", ""));
-    cmd.arg(body.service);
-    cmd.spawn().expect("failed to spawn");
+    cmd.arg(&body.service);
+
+    let mut child = cmd.spawn().expect("failed to spawn");
+
+    tokio::task::spawn_blocking(move || {
+        if child.wait().is_ok_and(|s| s.success()) {
+            events::publish(&body.service, EventKind::Installed);
+        }
+    });
 
     // ...
     Json(APIReturn::<String> {
@@ -192,13 +510,19 @@ pub async fn uninstall_request(
     // run sproc command
     let mut cmd = Command::new("sproc");
     cmd.arg("uninstall");
-    cmd.arg(body.service);
+    cmd.arg(&body.service);
 
     // ...
     Json(APIReturn::<String> {
         ok: true,
         data: match cmd.output() {
-            Ok(s) => s.status.to_string(),
+            Ok(s) => {
+                if s.status.success() {
+                    events::publish(&body.service, EventKind::Uninstalled);
+                }
+
+                s.status.to_string()
+            }
             Err(e) => {
                 return Json(APIReturn::<String> {
                     ok: false,
@@ -238,11 +562,23 @@ struct EditTemplate {
     package: (String, Service, String),
 }
 
+/// The combined usage of every running replica of a service (manage page)
+#[derive(Default)]
+struct Usage {
+    replicas: u32,
+    /// CPU usage (percent)
+    cpu: f32,
+    /// Memory usage (in MB)
+    memory: u64,
+}
+
 #[derive(Template)]
 #[template(path = "manage.html")]
 struct ManageTemplate {
     config: RegistryConfiguration,
-    services: Vec<(String, Service, bool)>,
+    services: Vec<(String, Service, bool, Usage)>,
+    tags: Vec<String>,
+    groups: Vec<(String, Vec<String>)>,
     key: String,
 }
 
@@ -352,23 +688,61 @@ pub async fn registry_manage_server_request(
         return Html("Not allowed".to_string());
     }
 
-    // service manager
+    // service manager (reading the configuration and sampling process usage blocks, the
+    // samples are taken `MINIMUM_CPU_UPDATE_INTERVAL` apart)
     let mut services = Vec::new();
-    let config = ServConf::get_config();
 
-    for service in config.services {
+    let (config, states, status) = match tokio::task::spawn_blocking(|| {
+        let config = ServConf::get_config();
+        let states = StateStore::read();
+        let status = config.status(&states);
+        (config, states, status)
+    })
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Html(e.to_string()),
+    };
+
+    // replicas are added up
+    let mut usage: HashMap<String, Usage> = HashMap::new();
+
+    for summary in status {
+        if !summary.state.is_active() {
+            continue;
+        }
+
+        let u = usage
+            .entry(model::replica_of(&summary.name).0.to_string())
+            .or_default();
+
+        u.replicas += 1;
+        u.cpu += summary.cpu.unwrap_or(0.0);
+        u.memory += summary.memory.unwrap_or(0) / 1048576;
+    }
+
+    for name in config.names() {
         services.push((
-            service.0.clone(),
-            service.1,
-            config.service_states.contains_key(&service.0),
+            name.clone(),
+            config.services[&name].clone(),
+            match states.get(&name) {
+                Some(s) => s.state.is_active(),
+                None => false,
+            },
+            usage.remove(&name).unwrap_or_default(),
         ));
     }
 
+    let mut groups: Vec<(String, Vec<String>)> = config.groups.clone().into_iter().collect();
+    groups.sort();
+
     // return
     Html(
         ManageTemplate {
             config: registry.0.registry.clone(),
             services,
+            tags: config.tags(),
+            groups,
             key: body.key.clone(),
         }
         .render()
@@ -442,14 +816,109 @@ pub async fn registry_delete_request(
 /// Sproc API endpoints
 pub fn sproc_api(config: ServConf) -> Router {
     Router::new()
+        .route("/ping", get(ping_request))
         .route("/start", post(observe_request))
         .route("/kill", post(kill_request))
+        .route("/group/start", post(group_start_request))
+        .route("/group/kill", post(group_kill_request))
         .route("/info", post(info_request))
+        .route("/logs", post(logs_request))
+        .route("/events", get(events_request))
+        .route("/metrics", get(metrics_request))
+        .route("/metrics/history", post(metrics_history_request))
         .route("/install", post(install_request))
         .route("/uninstall", post(uninstall_request))
         .with_state(config)
 }
 
+/// Start the background tasks needed by the sproc daemon
+pub fn start_tasks() {
+    tokio::task::spawn(logs::rotate_task());
+    tokio::task::spawn(timers::schedule_task());
+    tokio::task::spawn(metrics::sample_task());
+}
+
+/// Start the saved services, every service with `autostart` and the services whose `restart`
+/// policy keeps them running across server restarts (when the server starts)
+///
+/// The states of services whose process no longer exists (after a reboot) are removed first.
+/// Services whose process is still running are skipped, and because they aren't children of this
+/// server they can't be supervised again (a warning is printed for each of them).
+pub async fn resurrect() {
+    // services that were running (or waiting to restart) when the server stopped
+    let previous: Vec<String> = StateStore::read()
+        .into_iter()
+        .filter(|(_, s)| !matches!(s.state, ServiceState::Stopped | ServiceState::Stopping))
+        .map(|(n, _)| n)
+        .collect();
+
+    match StateStore::prune_stale() {
+        Ok(stale) => {
+            for name in stale {
+                println!("warn: removed stale state of service \"{name}\"");
+            }
+        }
+        Err(e) => println!("warn: failed to remove stale states: {e}"),
+    }
+
+    let config = ServConf::get_config();
+    let mut names = Vec::new();
+
+    let restarted = |name: &String, stopped: bool| {
+        config
+            .service(name)
+            .is_some_and(|s| s.restart.should_resurrect(stopped))
+    };
+
+    let previous = previous.into_iter().filter(|n| restarted(n, false));
+    let stopped = StateStore::stopped()
+        .into_iter()
+        .filter(|n| restarted(n, true));
+
+    for name in SavedServices::read()
+        .services
+        .into_iter()
+        .chain(config.autostart())
+        .chain(previous)
+        .chain(stopped)
+    {
+        if config.service(&name).is_none() {
+            println!("warn: saved service no longer exists: {name}");
+            continue;
+        }
+
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    // dependencies are started first
+    let order = match config.start_order(&names) {
+        Ok(o) => o,
+        Err(e) => {
+            println!("warn: failed to resurrect services: {e}");
+            return;
+        }
+    };
+
+    for name in order {
+        // applications are run by people, and timers on their schedule
+        if config.service(&name).map(|s| s.r#type) != Some(model::ServiceType::Service) {
+            continue;
+        }
+
+        if StateStore::get(&name).is_some_and(|s| s.state.is_active()) {
+            println!("warn: service {name} is still running from before the server started, its restart policy and health checks don't apply until it is restarted");
+            continue;
+        }
+
+        match Service::spawn(name.clone()).await {
+            Ok(_) => println!("info: resurrected service {name}"),
+            Err(e) => println!("warn: failed to resurrect service {name}: {e}"),
+        }
+    }
+}
+
 /// Registry API routes
 pub fn registry_api(config: ServConf) -> Router {
     Router::new()
@@ -0,0 +1,411 @@
+//! Shell-style command parsing
+use std::{
+    io::{Error, ErrorKind, Result},
+    iter::Peekable,
+    str::Chars,
+};
+
+/// Split a command into words the way a POSIX shell would
+///
+/// Supports single quotes, double quotes, backslash escapes and variable expansion (`$VAR`,
+/// `${VAR}` and `${VAR:-default}`, unset variables expand to nothing). Variables are expanded
+/// while the command is split, so their values are never split into multiple words (or parsed
+/// for quotes). Other shell features (pipes, redirections, command substitution, ...) are
+/// rejected, they need the command to be run with `shell = true`.
+pub fn split(input: &str, var: impl Fn(&str) -> Option<String>) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false; // quotes start a word even if it ends up empty
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated("'")),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.peek() {
+                            Some('"' | '\\' | '$' | '`') => word.push(chars.next().unwrap()),
+                            Some('\n') => {
+                                chars.next();
+                            }
+                            _ => word.push('\\'),
+                        },
+                        Some('$') => expand(&mut chars, &mut word, &var)?,
+                        Some('`') => return Err(unsupported("`")),
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated("\"")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => (),
+                Some(c) => {
+                    in_word = true;
+                    word.push(c);
+                }
+                None => return Err(unterminated("\\")),
+            },
+            '$' => {
+                // unquoted expansions that are empty don't create a word
+                let len = word.len();
+                expand(&mut chars, &mut word, &var)?;
+
+                if word.len() > len {
+                    in_word = true;
+                }
+            }
+            '#' if !in_word => break, // comment
+            '|' | '&' | ';' | '<' | '>' | '(' | ')' | '`' => {
+                return Err(unsupported(&c.to_string()))
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+/// Expand a variable (after a `$`) into `word`
+fn expand(
+    chars: &mut Peekable<Chars>,
+    word: &mut String,
+    var: &impl Fn(&str) -> Option<String>,
+) -> Result<()> {
+    let mut name = String::new();
+
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+
+            while let Some(c) = chars.peek() {
+                if !c.is_ascii_alphanumeric() && (*c != '_') {
+                    break;
+                }
+
+                name.push(*c);
+                chars.next();
+            }
+
+            let default = match chars.next() {
+                Some('}') => None,
+                Some(':') if chars.peek() == Some(&'-') => {
+                    chars.next();
+                    Some(default(chars)?)
+                }
+                Some(c) => return Err(unsupported(&format!("${{{name}{c}"))),
+                None => return Err(unterminated("${")),
+            };
+
+            if !is_name(&name) {
+                return Err(unsupported(&format!("${{{name}}}")));
+            }
+
+            // defaults are used when the variable is unset or empty
+            if let Some(default) = default {
+                match var(&name) {
+                    Some(value) if !value.is_empty() => word.push_str(&value),
+                    _ => word.push_str(&expand_all(&default, var)?),
+                }
+
+                return Ok(());
+            }
+        }
+        Some(c) if c.is_ascii_alphabetic() || (*c == '_') => {
+            while let Some(c) = chars.peek() {
+                if !c.is_ascii_alphanumeric() && (*c != '_') {
+                    break;
+                }
+
+                name.push(*c);
+                chars.next();
+            }
+        }
+        Some('(') => return Err(unsupported("$(")),
+        _ => {
+            // not an expansion
+            word.push('$');
+            return Ok(());
+        }
+    }
+
+    if let Some(value) = var(&name) {
+        word.push_str(&value);
+    }
+
+    Ok(())
+}
+
+/// Read the default of a `${VAR:-default}` expansion (after the `:-`), up to its closing brace
+fn default(chars: &mut Peekable<Chars>) -> Result<String> {
+    let mut default = String::new();
+    let mut depth = 1; // defaults can contain other expansions
+
+    loop {
+        match chars.next() {
+            Some('$') if chars.peek() == Some(&'{') => {
+                chars.next();
+                depth += 1;
+                default.push_str("${");
+            }
+            Some('}') => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Ok(default);
+                }
+
+                default.push('}');
+            }
+            Some(c) => default.push(c),
+            None => return Err(unterminated("${")),
+        }
+    }
+}
+
+/// Expand every variable in `input` (quotes aren't special)
+fn expand_all(input: &str, var: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => expand(&mut chars, &mut out, var)?,
+            c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Get the names of the variables used in a command (`$VAR` and `${VAR}`, including the
+/// ones used in defaults)
+///
+/// Variables that wouldn't be expanded (like `'$VAR'`) are included too.
+pub fn variables(input: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = input;
+
+    while let Some(i) = rest.find('$') {
+        rest = &rest[i + 1..];
+
+        let name: String = rest
+            .strip_prefix('{')
+            .unwrap_or(rest)
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || (*c == '_'))
+            .collect();
+
+        if is_name(&name) && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+/// Quote a word so [`split`] reads it as the same single word
+pub fn quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    }
+
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// If `name` is a valid variable name
+fn is_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || (c == '_') => name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || (c == '_')),
+        _ => false,
+    }
+}
+
+fn unterminated(token: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("Unterminated `{token}` in command."),
+    )
+}
+
+fn unsupported(token: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("Unsupported \"{token}\" in command, set `shell = true` to use shell features."),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(var: &str) -> Option<String> {
+        match var {
+            "NAME" => Some("world".to_string()),
+            "QUOTE" => Some("hello 'world".to_string()),
+            "SPACES" => Some("a b  c".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn words(input: &str) -> Vec<String> {
+        split(input, vars).unwrap()
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(words("  echo a\tb\nc  "), ["echo", "a", "b", "c"]);
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn single_quotes() {
+        assert_eq!(words("echo 'a b' 'it''s'"), ["echo", "a b", "its"]);
+        assert_eq!(words(r#"echo '"$NAME" \n'"#), ["echo", r#""$NAME" \n"#]);
+    }
+
+    #[test]
+    fn double_quotes() {
+        assert_eq!(words(r#"echo "a b" "x"'y'z"#), ["echo", "a b", "xyz"]);
+        assert_eq!(words(r#"echo "\"\\\$ \n""#), ["echo", r#""\$ \n"#]);
+        assert_eq!(words(r#"echo "hello $NAME""#), ["echo", "hello world"]);
+    }
+
+    #[test]
+    fn backslash_escapes() {
+        assert_eq!(words(r"echo a\ b \'c\' \#"), ["echo", "a b", "'c'", "#"]);
+        assert_eq!(words("echo a\\\nb"), ["echo", "ab"]);
+    }
+
+    #[test]
+    fn empty_quoted_args() {
+        assert_eq!(words("echo '' \"\" x"), ["echo", "", "", "x"]);
+    }
+
+    #[test]
+    fn trailing_comments() {
+        assert_eq!(words("echo a # comment 'unterminated"), ["echo", "a"]);
+        assert_eq!(words("echo a#b '#'"), ["echo", "a#b", "#"]);
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        assert_eq!(
+            words("echo $NAME ${NAME}s x$NAME"),
+            ["echo", "world", "worlds", "xworld"]
+        );
+        assert_eq!(words("echo $ a$ $1"), ["echo", "$", "a$", "$1"]);
+    }
+
+    #[test]
+    fn unset_variables_expand_to_nothing() {
+        assert_eq!(
+            words("echo $UNSET ${EMPTY} \"$UNSET\" x"),
+            ["echo", "", "x"]
+        );
+    }
+
+    #[test]
+    fn expanded_values_are_never_split() {
+        assert_eq!(words("echo $QUOTE"), ["echo", "hello 'world"]);
+        assert_eq!(
+            words("echo ${SPACES} \"$SPACES\""),
+            ["echo", "a b  c", "a b  c"]
+        );
+        assert_eq!(words("echo '$SPACES'"), ["echo", "$SPACES"]);
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(words("echo ${UNSET:-a b}"), ["echo", "a b"]);
+        assert_eq!(words("echo ${EMPTY:-x} ${NAME:-x}"), ["echo", "x", "world"]);
+        assert_eq!(words("echo ${UNSET:-${QUOTE}!}"), ["echo", "hello 'world!"]);
+    }
+
+    #[test]
+    fn unterminated_quotes() {
+        for input in [
+            "echo 'a",
+            "echo \"a",
+            "echo a\\",
+            "echo ${NAME",
+            "echo ${UNSET:-a",
+        ] {
+            let e = split(input, vars).unwrap_err();
+            assert!(e.to_string().starts_with("Unterminated"), "{input}: {e}");
+        }
+    }
+
+    #[test]
+    fn shell_features_are_rejected() {
+        for input in [
+            "a | b",
+            "a && b",
+            "a; b",
+            "a > b",
+            "a < b",
+            "(a)",
+            "echo `a`",
+            "echo $(a)",
+            "echo \"`a`\"",
+            "echo ${1}",
+            "echo ${a b}",
+        ] {
+            let e = split(input, vars).unwrap_err();
+            assert!(e.to_string().starts_with("Unsupported"), "{input}: {e}");
+        }
+
+        // quoted operators are just characters
+        assert_eq!(words(r#"echo '|' "&&" \;"#), ["echo", "|", "&&", ";"]);
+    }
+
+    #[test]
+    fn quoted_words_are_read_back() {
+        assert_eq!(quote("a-b/c.d"), "a-b/c.d");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("it's $HOME"), r"'it'\''s $HOME'");
+
+        for word in ["", "a b", "it's", "$NAME", "#", "a\\b", "\"x\"", "a|b"] {
+            assert_eq!(words(&quote(word)), [word]);
+        }
+    }
+
+    #[test]
+    fn variable_names() {
+        assert_eq!(
+            variables("echo $A ${B} ${C:-$D} $A $1 $ ${}"),
+            ["A", "B", "C", "D"]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Sproc Unix socket API (the same API as the HTTP server, for local control)
+use axum::Router;
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, Method, Request, StatusCode};
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use std::{
+    io::{Error, ErrorKind, Result},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+use tokio::net::{UnixListener, UnixStream};
+
+/// If a process with the given user ID can use the socket (the user running the server, or root)
+fn is_allowed(uid: u32) -> bool {
+    (uid == 0) || (uid == unsafe { libc::geteuid() })
+}
+
+/// Serve an app on a Unix socket
+///
+/// The socket file can only be used by the user running the server (mode `0600`), and the
+/// peer credentials (`SO_PEERCRED`) of every connection are checked so only processes of the
+/// same user (or root) are served.
+pub async fn serve(path: &Path, app: Router) -> Result<()> {
+    if path.exists() {
+        // another server is already listening
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(Error::new(
+                ErrorKind::AddrInUse,
+                format!("Socket is already in use. ({})", path.display()),
+            ));
+        }
+
+        // left behind by a server that stopped
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    println!("info: listening on unix socket {}", path.display());
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                println!("warn: failed to accept socket connection: {e}");
+                continue;
+            }
+        };
+
+        // check peer credentials
+        match stream.peer_cred() {
+            Ok(c) if is_allowed(c.uid()) => (),
+            Ok(c) => {
+                println!("warn: refused socket connection from user {}", c.uid());
+                continue;
+            }
+            Err(e) => {
+                println!("warn: failed to read socket peer credentials: {e}");
+                continue;
+            }
+        }
+
+        let service = TowerToHyperService::new(app.clone());
+
+        tokio::task::spawn(async move {
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades()
+                .await;
+        });
+    }
+}
+
+/// Send a request to a server through its Unix socket
+///
+/// Returns the status and body of the response. Fails with [`ErrorKind::NotConnected`] if the
+/// socket can't be connected to.
+pub async fn request(
+    path: &Path,
+    method: Method,
+    uri: &str,
+    body: Vec<u8>,
+) -> Result<(StatusCode, Bytes)> {
+    let stream = match UnixStream::connect(path).await {
+        Ok(s) => s,
+        Err(e) => return Err(Error::new(ErrorKind::NotConnected, e.to_string())),
+    };
+
+    let (mut sender, connection) =
+        match hyper::client::conn::http1::handshake(TokioIo::new(stream)).await {
+            Ok(c) => c,
+            Err(e) => return Err(Error::new(ErrorKind::NotConnected, e.to_string())),
+        };
+
+    tokio::task::spawn(connection);
+
+    let request = match Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Host", "localhost")
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body)))
+    {
+        Ok(r) => r,
+        Err(e) => return Err(Error::new(ErrorKind::InvalidInput, e.to_string())),
+    };
+
+    let res = match sender.send_request(request).await {
+        Ok(r) => r,
+        Err(e) => return Err(Error::new(ErrorKind::ConnectionAborted, e.to_string())),
+    };
+
+    let status = res.status();
+
+    match res.into_body().collect().await {
+        Ok(b) => Ok((status, b.to_bytes())),
+        Err(e) => Err(Error::new(ErrorKind::ConnectionAborted, e.to_string())),
+    }
+}
@@ -0,0 +1,254 @@
+//! Sproc runtime state (`~/.config/xsu-apps/sproc/state.toml`)
+//!
+//! Service states are kept apart from the pinned configuration, so running services never
+//! requires rewriting `services.toml`. Every change is made while holding an exclusive lock on
+//! the state file, and the file is replaced atomically.
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{Error, ErrorKind, Result, Write},
+};
+use sysinfo::System;
+
+use crate::model::{ServiceStates, ServiceStatus};
+use crate::unix;
+
+/// The contents of the state file
+#[derive(Serialize, Deserialize, Default)]
+struct StateFile {
+    /// Service states
+    #[serde(default)]
+    services: ServiceStates,
+    /// Services that were stopped with `sproc kill` (and haven't been started since)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stopped: Vec<String>,
+}
+
+/// The services that were running when `sproc save` was last run (`saved.toml`)
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct SavedServices {
+    /// The names of the saved services
+    #[serde(default)]
+    pub services: Vec<String>,
+}
+
+impl SavedServices {
+    /// The path of the saved services file
+    pub fn path() -> String {
+        format!("{}/saved.toml", StateStore::dir())
+    }
+
+    /// Read the saved services (nothing is saved if the file doesn't exist)
+    pub fn read() -> Self {
+        match std::fs::read_to_string(Self::path()) {
+            Ok(c) => match toml::from_str(&c) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("warn: failed to read saved services: {e}");
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Save the services that are running now, returning their names
+    pub fn save() -> Result<Vec<String>> {
+        let mut services: Vec<String> = StateStore::read()
+            .into_iter()
+            .filter(|(_, s)| s.state.is_active())
+            .map(|(n, _)| n)
+            .collect();
+
+        services.sort();
+
+        let saved = Self { services };
+
+        let contents = match toml::to_string_pretty(&saved) {
+            Ok(c) => c,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        };
+
+        std::fs::create_dir_all(StateStore::dir())?;
+        std::fs::write(Self::path(), contents)?;
+
+        Ok(saved.services)
+    }
+}
+
+/// Service state storage
+pub struct StateStore;
+
+impl StateStore {
+    /// The directory the state file is stored in
+    fn dir() -> String {
+        let home = env::var("HOME").expect("failed to read $HOME");
+        format!("{home}/.config/xsu-apps/sproc")
+    }
+
+    /// The path of the state file
+    pub fn path() -> String {
+        format!("{}/state.toml", Self::dir())
+    }
+
+    /// Take the state lock (released when the returned file is dropped)
+    fn lock(exclusive: bool) -> Result<File> {
+        std::fs::create_dir_all(Self::dir())?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}/state.lock", Self::dir()))?;
+
+        unix::lock_file(&file, exclusive)?;
+        Ok(file)
+    }
+
+    /// Read the state file (lock must be held)
+    fn load() -> StateFile {
+        match std::fs::read_to_string(Self::path()) {
+            Ok(c) => match toml::from_str::<StateFile>(&c) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("warn: failed to read state file: {e}");
+                    StateFile::default()
+                }
+            },
+            Err(_) => StateFile::default(),
+        }
+    }
+
+    /// Replace the state file (lock must be held)
+    fn save(file: &StateFile) -> Result<()> {
+        let contents = match toml::to_string_pretty(file) {
+            Ok(c) => c,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+        };
+
+        // write to a temporary file first so the state file is never partially written
+        let tmp = format!("{}.tmp", Self::path());
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+
+        std::fs::rename(tmp, Self::path())
+    }
+
+    /// Read the whole state file
+    fn read_file() -> StateFile {
+        match Self::lock(false) {
+            Ok(_lock) => Self::load(),
+            Err(e) => {
+                println!("warn: failed to lock state file: {e}");
+                Self::load()
+            }
+        }
+    }
+
+    /// Change the whole state file (see [`StateStore::update`])
+    fn update_file<T>(f: impl FnOnce(&mut StateFile) -> T) -> Result<T> {
+        let _lock = Self::lock(true)?;
+
+        let mut file = Self::load();
+        let res = f(&mut file);
+        Self::save(&file)?;
+
+        Ok(res)
+    }
+
+    /// Read all service states
+    pub fn read() -> ServiceStates {
+        Self::read_file().services
+    }
+
+    /// Get the state of a single service
+    pub fn get(name: &String) -> Option<ServiceStatus> {
+        Self::read().remove(name)
+    }
+
+    /// Change service states
+    ///
+    /// `f` is run while the state file is locked, so no other process can change states until
+    /// the changes are written.
+    pub fn update<T>(f: impl FnOnce(&mut ServiceStates) -> T) -> Result<T> {
+        Self::update_file(|file| f(&mut file.services))
+    }
+
+    /// Set the state of a service
+    ///
+    /// A service that has a state again is no longer considered stopped with `sproc kill`.
+    pub fn set(name: &String, status: ServiceStatus) -> Result<()> {
+        Self::update_file(|file| {
+            file.services.insert(name.to_owned(), status);
+            file.stopped.retain(|n| n != name);
+        })
+    }
+
+    /// Remove the state of a service
+    pub fn remove(name: &String) -> Result<Option<ServiceStatus>> {
+        Self::update(|states| states.remove(name))
+    }
+
+    /// Remove the state of a service that was stopped with `sproc kill`, remembering that it was
+    /// stopped (so `unless-stopped` services stay stopped when the server restarts)
+    pub fn stop(name: &String) -> Result<()> {
+        Self::update_file(|file| {
+            file.services.remove(name);
+
+            if !file.stopped.contains(name) {
+                file.stopped.push(name.to_owned());
+            }
+        })
+    }
+
+    /// Get the services that were stopped with `sproc kill` (and haven't been started since)
+    pub fn stopped() -> Vec<String> {
+        Self::read_file().stopped
+    }
+
+    /// Remove the state of a service whose process is stale (exited, or its PID now belongs to
+    /// another process), returning the error to report
+    pub fn remove_stale(name: &String) -> Error {
+        if let Err(e) = Self::remove(name) {
+            return e;
+        }
+
+        Error::new(
+            ErrorKind::NotConnected,
+            format!("Service process is stale, removed its state. ({name})"),
+        )
+    }
+
+    /// Remove the states of all running services whose process is stale, returning their names
+    ///
+    /// The state file is only rewritten if a stale state was found.
+    pub fn prune_stale() -> Result<Vec<String>> {
+        let mut sys = System::new();
+        sys.refresh_processes();
+
+        let is_stale =
+            |status: &ServiceStatus| status.state.is_active() && status.process(&sys).is_none();
+
+        if !Self::read().values().any(is_stale) {
+            return Ok(Vec::new());
+        }
+
+        Self::update(|states| {
+            let mut stale = Vec::new();
+
+            for (name, status) in states.iter() {
+                if is_stale(status) {
+                    stale.push(name.to_owned());
+                }
+            }
+
+            for name in &stale {
+                states.remove(name);
+            }
+
+            stale
+        })
+    }
+}
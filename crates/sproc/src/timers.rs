@@ -0,0 +1,210 @@
+//! Sproc timer services (`type = "Timer"`)
+use chrono::{DateTime, Local, TimeZone};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Error, ErrorKind, Result},
+    str::FromStr,
+};
+
+use crate::events::{self, EventKind};
+use crate::hooks::{self, Hook};
+use crate::logs::ServiceLogs;
+use crate::model::{
+    self, Service, ServiceState, ServiceStates, ServiceStatus, ServiceType, ServicesConfiguration,
+};
+use crate::state::StateStore;
+
+/// Parse a cron expression
+///
+/// Expressions can have 5 fields (`min hour day month weekday`), or 6-7 fields (with seconds
+/// first and an optional year last). Shortcuts like `@daily` are also accepted.
+pub fn parse_schedule(expression: &str) -> Result<Schedule> {
+    let expression = expression.trim();
+
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {expression}")
+    } else {
+        expression.to_string()
+    };
+
+    match Schedule::from_str(&expression) {
+        Ok(s) => Ok(s),
+        Err(e) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid schedule \"{expression}\": {e}"),
+        )),
+    }
+}
+
+/// Get the schedule of a timer service
+pub fn schedule(service: &Service) -> Result<Schedule> {
+    match service.schedule {
+        Some(ref s) => parse_schedule(s),
+        None => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Timer services must have a schedule.",
+        )),
+    }
+}
+
+/// Run a timer service once, recording the run in its state
+///
+/// The run is skipped if the previous run is still running (unless `skip_overlapping` is
+/// disabled).
+pub fn fire(name: &String, service: &Service) -> Result<()> {
+    if let Some(s) = StateStore::get(name) {
+        if s.state.is_active() && service.skip_overlapping {
+            println!("info: skipping run of timer \"{name}\" (previous run is still running)");
+            return Ok(());
+        }
+    }
+
+    // start
+    println!("info: running timer \"{name}\"");
+
+    let mut child = service.start_process(name)?;
+    let pid = child.id();
+
+    StateStore::set(
+        name,
+        ServiceStatus {
+            last_run: Local::now().timestamp() as u64,
+            ..ServiceStatus::running(pid)
+        },
+    )?;
+
+    // record exit status
+    let name = name.to_owned();
+    let service = service.clone();
+
+    tokio::task::spawn(async move {
+        let status = match tokio::task::spawn_blocking(move || child.wait()).await {
+            Ok(Ok(s)) => s,
+            _ => return,
+        };
+
+        let res = StateStore::update(|states| match states.get_mut(&name) {
+            // the state belongs to a newer run, or the timer was stopped with `sproc kill`
+            Some(s) if (s.pid == pid) && (s.state == ServiceState::Running) => {
+                *s = ServiceStatus {
+                    state: ServiceState::Stopped,
+                    exit_code: status.code(),
+                    last_run: s.last_run,
+                    ..Default::default()
+                };
+
+                true
+            }
+            _ => false,
+        });
+
+        match res {
+            Ok(true) => {
+                ServiceLogs::new(&name).event(&format!("exited ({status})"));
+                events::publish(&name, EventKind::exited(&status));
+                let _ = tokio::task::spawn_blocking(move || {
+                    hooks::run_or_warn(&name, &service, Hook::PostStop)
+                })
+                .await;
+            }
+            Ok(false) => (),
+            Err(e) => println!("warn: failed to record run of timer \"{name}\": {e}"),
+        }
+    });
+
+    Ok(())
+}
+
+/// Run timer services on their schedules (never returns)
+pub async fn schedule_task() {
+    let mut checked = Local::now();
+
+    loop {
+        let config = ServicesConfiguration::get_config();
+
+        // wait for the next timer to fire (checking the config for changes at least every minute)
+        let mut wake = checked + chrono::Duration::seconds(60);
+
+        for (name, service) in &config.services {
+            // templates are only run as instances (with `sproc run`)
+            if (service.r#type != ServiceType::Timer) || model::is_template(name) {
+                continue;
+            }
+
+            if let Ok(schedule) = schedule(service) {
+                if let Some(next) = schedule.after(&checked).next() {
+                    wake = wake.min(next);
+                }
+            }
+        }
+
+        if let Ok(wait) = (wake - Local::now()).to_std() {
+            tokio::time::sleep(wait).await;
+        }
+
+        // fire timers that were due
+        let now = Local::now();
+        let config = ServicesConfiguration::get_config();
+
+        for (name, service) in &config.services {
+            if (service.r#type != ServiceType::Timer) || model::is_template(name) {
+                continue;
+            }
+
+            let schedule = match schedule(service) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("warn: timer \"{name}\" can't be run: {e}");
+                    continue;
+                }
+            };
+
+            match schedule.after(&checked).next() {
+                Some(next) if next <= now => (),
+                _ => continue,
+            }
+
+            // dependencies are started along with the timer
+            if let Err(e) = Service::spawn(name.to_owned()).await {
+                println!("warn: failed to run timer \"{name}\": {e}");
+            }
+        }
+
+        checked = now;
+    }
+}
+
+/// General information about a timer [`Service`]
+#[derive(Serialize, Deserialize)]
+pub struct TimerInfo {
+    pub name: String,
+    pub schedule: String,
+    pub running: bool,
+    pub next_run: Option<String>,
+    pub last_run: Option<String>,
+    pub last_exit_code: Option<i32>,
+}
+
+/// Get timer service info
+pub fn info(name: &String, service: &Service, service_states: &ServiceStates) -> TimerInfo {
+    let state = service_states.get(name);
+
+    TimerInfo {
+        name: name.to_owned(),
+        schedule: service.schedule.clone().unwrap_or_default(),
+        running: state.map(|s| s.state.is_active()).unwrap_or(false),
+        next_run: match schedule(service) {
+            Ok(s) => s.upcoming(Local).next().map(|t| t.to_rfc3339()),
+            Err(_) => None,
+        },
+        last_run: match state {
+            Some(s) if s.last_run != 0 => Local
+                .timestamp_opt(s.last_run as i64, 0)
+                .single()
+                .map(|t: DateTime<Local>| t.to_rfc3339()),
+            _ => None,
+        },
+        last_exit_code: state.and_then(|s| s.exit_code),
+    }
+}
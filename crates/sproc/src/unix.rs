@@ -0,0 +1,274 @@
+//! Unix process helpers
+use std::io::{Error, ErrorKind, Result};
+
+/// Get a signal number from its name (`SIGTERM`, `TERM`) or number (`15`)
+pub fn parse_signal(name: &str) -> Result<i32> {
+    if let Ok(n) = name.parse::<i32>() {
+        return Ok(n);
+    }
+
+    let upper = name.to_uppercase();
+
+    Ok(match upper.strip_prefix("SIG").unwrap_or(&upper) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "WINCH" => libc::SIGWINCH,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown signal. ({name})"),
+            ))
+        }
+    })
+}
+
+/// Send a signal to the process group led by `pid`
+///
+/// Falls back to signaling only `pid` if it doesn't lead a process group (services started
+/// before process groups were used).
+pub fn signal_group(pid: u32, signal: i32) -> Result<()> {
+    let pid = pid as libc::pid_t;
+
+    if unsafe { libc::kill(-pid, signal) } == 0 {
+        return Ok(());
+    }
+
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        return Ok(());
+    }
+
+    Err(Error::last_os_error())
+}
+
+/// If any process in the process group led by `pid` (or `pid` itself) is still alive
+pub fn group_alive(pid: u32) -> bool {
+    let pid = pid as libc::pid_t;
+    (unsafe { libc::kill(-pid, 0) } == 0) || (unsafe { libc::kill(pid, 0) } == 0)
+}
+
+/// Lock a file with `flock`, blocking until the lock is acquired
+///
+/// The lock is released when the file is closed.
+pub fn lock_file(file: &std::fs::File, exclusive: bool) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let operation = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// A user account
+#[derive(Debug, Clone)]
+pub struct Account {
+    /// Account name
+    pub name: String,
+    /// User ID
+    pub uid: u32,
+    /// Primary group ID
+    pub gid: u32,
+    /// Home directory
+    pub home: String,
+}
+
+/// Run a `get*_r` lookup function, growing the buffer while it is too small
+///
+/// `f` must copy what it needs out of the entry, as the entry points into the buffer.
+/// Returns `None` if the entry doesn't exist.
+fn lookup<T>(mut f: impl FnMut(&mut Vec<libc::c_char>) -> (i32, Option<T>)) -> Result<Option<T>> {
+    let mut buffer: Vec<libc::c_char> = vec![0; 4096];
+
+    loop {
+        match f(&mut buffer) {
+            (0, entry) => return Ok(entry),
+            (libc::ERANGE, _) if buffer.len() < (1 << 20) => {
+                let len = buffer.len() * 2;
+                buffer.resize(len, 0);
+            }
+            (code, _) => return Err(Error::from_raw_os_error(code)),
+        }
+    }
+}
+
+/// Get a user account by its name
+pub fn lookup_user(name: &str) -> Result<Account> {
+    let c_name = match std::ffi::CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return Err(Error::new(ErrorKind::InvalidInput, "Invalid user name.")),
+    };
+
+    let account = lookup(|buffer| {
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        if (code != 0) || result.is_null() {
+            return (code, None);
+        }
+
+        (
+            0,
+            Some(Account {
+                name: name.to_string(),
+                uid: passwd.pw_uid,
+                gid: passwd.pw_gid,
+                home: unsafe { std::ffi::CStr::from_ptr(passwd.pw_dir) }
+                    .to_string_lossy()
+                    .to_string(),
+            }),
+        )
+    })?;
+
+    match account {
+        Some(a) => Ok(a),
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("User does not exist. ({name})"),
+        )),
+    }
+}
+
+/// Get the ID of a group by its name
+pub fn lookup_group(name: &str) -> Result<u32> {
+    let c_name = match std::ffi::CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return Err(Error::new(ErrorKind::InvalidInput, "Invalid group name.")),
+    };
+
+    let gid = lookup(|buffer| {
+        let mut group: libc::group = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::group = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                &mut group,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        if (code != 0) || result.is_null() {
+            return (code, None);
+        }
+
+        (0, Some(group.gr_gid))
+    })?;
+
+    match gid {
+        Some(g) => Ok(g),
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Group does not exist. ({name})"),
+        )),
+    }
+}
+
+/// Get the groups a user is a member of (including `gid`), the same groups `initgroups` sets
+///
+/// This is looked up before the service process is forked, since reading the group database
+/// isn't safe between `fork` and `exec`.
+pub fn user_groups(user: &Account, gid: u32) -> Result<Vec<u32>> {
+    let c_name = match std::ffi::CString::new(user.name.as_str()) {
+        Ok(n) => n,
+        Err(_) => return Err(Error::new(ErrorKind::InvalidInput, "Invalid user name.")),
+    };
+
+    let mut groups: Vec<libc::gid_t> = vec![0; 32];
+
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let code =
+            unsafe { libc::getgrouplist(c_name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+
+        if code >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+
+        // `count` is set to the number of groups the user is in when the buffer is too small
+        if (count as usize) <= groups.len() || groups.len() >= 65536 {
+            return Err(Error::other(format!(
+                "Failed to read the groups of user \"{}\".",
+                user.name
+            )));
+        }
+
+        groups.resize(count as usize, 0);
+    }
+}
+
+/// The user and groups a process is run as
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// The user to switch to
+    pub user: Option<Account>,
+    /// The primary group to switch to
+    pub gid: Option<u32>,
+    /// Supplementary groups (replacing the current ones), including the groups of `user`
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Switch the current process to these credentials
+    ///
+    /// This is run in the forked service process before `exec`, so it must not allocate.
+    /// Groups are changed first, as they can't be changed once the user has been switched.
+    pub fn apply(&self) -> Result<()> {
+        // only root can change supplementary groups, but it must replace its own
+        if (!self.groups.is_empty() || (unsafe { libc::geteuid() } == 0))
+            && (unsafe { libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) } != 0)
+        {
+            return Err(Error::last_os_error());
+        }
+
+        if let Some(gid) = self.gid {
+            if unsafe { libc::setgid(gid) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        if let Some(ref user) = self.user {
+            if unsafe { libc::setuid(user.uid) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the hostname of this machine
+pub fn hostname() -> String {
+    let mut buffer = [0 as libc::c_char; 256];
+
+    if unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) } != 0 {
+        return String::new();
+    }
+
+    unsafe { std::ffi::CStr::from_ptr(buffer.as_ptr()) }
+        .to_string_lossy()
+        .to_string()
+}
@@ -0,0 +1,316 @@
+//! Sproc configuration variables (env files and `${VAR}` interpolation)
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+};
+
+/// Replace every `${VAR}` and `${VAR:-default}` in `input` with the value of the variable
+///
+/// `default` is used when the variable is unset or empty (and can be interpolated itself).
+/// Variables without a default that can't be found are left as they are, so they can still be
+/// expanded later (by the command parser or a shell).
+pub fn interpolate(input: &str, var: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    interpolate_with(input, var, false)
+}
+
+/// Like [`interpolate`], but fails if a variable without a default can't be found (for values
+/// that are never expanded later, like paths)
+pub fn interpolate_strict(input: &str, var: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    interpolate_with(input, var, true)
+}
+
+fn interpolate_with(
+    input: &str,
+    var: &impl Fn(&str) -> Option<String>,
+    strict: bool,
+) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        // find the matching brace (defaults can contain other variables)
+        let mut depth = 1;
+        let mut end = None;
+        let mut chars = after.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '$' if matches!(chars.peek(), Some((_, '{'))) => {
+                    chars.next();
+                    depth += 1;
+                }
+                '}' => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        end = Some(i);
+                        break;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let end = match end {
+            Some(e) => e,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unterminated \"${{\" in \"{input}\"."),
+                ))
+            }
+        };
+
+        let expression = &after[..end];
+
+        match expression.split_once(":-") {
+            Some((name, default)) => match var(name) {
+                Some(value) if !value.is_empty() => out.push_str(&value),
+                _ => out.push_str(&interpolate_with(default, var, strict)?),
+            },
+            None => match var(expression) {
+                Some(value) => out.push_str(&value),
+                None if strict => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Variable \"{expression}\" is not set in \"{input}\"."),
+                    ))
+                }
+                None => {
+                    out.push_str("${");
+                    out.push_str(expression);
+                    out.push('}');
+                }
+            },
+        }
+
+        rest = &after[end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parse the contents of a dotenv file
+///
+/// Lines are `KEY=VALUE` (optionally starting with `export`). Values can be double quoted
+/// (with `\n`, `\"` and `\\` escapes) or single quoted (used as-is), unquoted values are
+/// trimmed and end at ` #`. Values that aren't single quoted are interpolated (with the keys
+/// set on earlier lines, and then `var`).
+pub fn parse_env_file(
+    contents: &str,
+    var: &impl Fn(&str) -> Option<String>,
+) -> Result<HashMap<String, String>> {
+    let mut env: HashMap<String, String> = HashMap::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let var = |name: &str| match env.get(name) {
+            Some(v) => Some(v.to_owned()),
+            None => var(name),
+        };
+
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid env file line {}, expected KEY=VALUE.", i + 1),
+                ))
+            }
+        };
+
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            match quoted.find('\'') {
+                Some(end) => quoted[..end].to_string(),
+                None => return Err(unterminated_quote(i)),
+            }
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = quoted.chars();
+            let mut terminated = false;
+
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => {
+                        terminated = true;
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some('n') => unescaped.push('\n'),
+                        Some(c) => unescaped.push(c),
+                        None => break,
+                    },
+                    c => unescaped.push(c),
+                }
+            }
+
+            if !terminated {
+                return Err(unterminated_quote(i));
+            }
+
+            interpolate(&unescaped, &var)?
+        } else {
+            let value = match value.find(" #") {
+                Some(comment) => value[..comment].trim_end(),
+                None => value,
+            };
+
+            interpolate(value, &var)?
+        };
+
+        env.insert(key.to_string(), value);
+    }
+
+    Ok(env)
+}
+
+fn unterminated_quote(line: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Unterminated quote on env file line {}.", line + 1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(var: &str) -> Option<String> {
+        match var {
+            "NAME" => Some("world".to_string()),
+            "DIR" => Some("/srv".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn env(contents: &str) -> HashMap<String, String> {
+        parse_env_file(contents, &vars).unwrap()
+    }
+
+    #[test]
+    fn variables_are_interpolated() {
+        assert_eq!(
+            interpolate("hello ${NAME}!", &vars).unwrap(),
+            "hello world!"
+        );
+        assert_eq!(interpolate("${DIR}/${NAME}", &vars).unwrap(), "/srv/world");
+        assert_eq!(interpolate("$NAME ${EMPTY}.", &vars).unwrap(), "$NAME .");
+    }
+
+    #[test]
+    fn unknown_variables_are_left_as_is() {
+        assert_eq!(interpolate("a ${UNSET} b", &vars).unwrap(), "a ${UNSET} b");
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(interpolate("${UNSET:-x y}", &vars).unwrap(), "x y");
+        assert_eq!(interpolate("${EMPTY:-x}", &vars).unwrap(), "x");
+        assert_eq!(interpolate("${NAME:-x}", &vars).unwrap(), "world");
+        assert_eq!(interpolate("${UNSET:-}", &vars).unwrap(), "");
+    }
+
+    #[test]
+    fn nested_defaults() {
+        assert_eq!(
+            interpolate("${UNSET:-${DIR}/app}", &vars).unwrap(),
+            "/srv/app"
+        );
+        assert_eq!(
+            interpolate("${UNSET:-${ALSO_UNSET:-${NAME}}}", &vars).unwrap(),
+            "world"
+        );
+    }
+
+    #[test]
+    fn unterminated_variables() {
+        for input in ["${NAME", "a ${UNSET:-${NAME}"] {
+            let e = interpolate(input, &vars).unwrap_err();
+            assert!(e.to_string().starts_with("Unterminated"), "{input}: {e}");
+        }
+    }
+
+    #[test]
+    fn strict_interpolation() {
+        assert_eq!(
+            interpolate_strict("${DIR}/${UNSET:-app}", &vars).unwrap(),
+            "/srv/app"
+        );
+
+        for input in ["${UNSET}/app", "${UNSET:-${ALSO_UNSET}}"] {
+            let e = interpolate_strict(input, &vars).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::NotFound, "{input}: {e}");
+        }
+    }
+
+    #[test]
+    fn env_file_values() {
+        let env = env(concat!(
+            "# comment\n",
+            "\n",
+            "PLAIN = a b  \n",
+            "export EXPORTED=1\n",
+            "DOUBLE=\"a \\\"b\\\" \\\\ c\\nd\"\n",
+            "SINGLE='${NAME} \\n'\n",
+            "COMMENT=value # comment\n",
+            "HASH=a#b\n",
+            "EMPTY=\n",
+        ));
+
+        assert_eq!(env.len(), 7);
+        assert_eq!(env["PLAIN"], "a b");
+        assert_eq!(env["EXPORTED"], "1");
+        assert_eq!(env["DOUBLE"], "a \"b\" \\ c\nd");
+        assert_eq!(env["SINGLE"], "${NAME} \\n");
+        assert_eq!(env["COMMENT"], "value");
+        assert_eq!(env["HASH"], "a#b");
+        assert_eq!(env["EMPTY"], "");
+    }
+
+    #[test]
+    fn env_file_interpolation() {
+        let env = env(concat!(
+            "A=${NAME}\n",
+            "B=\"${A}2\"\n",
+            "C=${UNSET:-${B}}\n",
+            "D='${A}'\n",
+            "E=${UNSET}\n",
+        ));
+
+        assert_eq!(env["A"], "world");
+        assert_eq!(env["B"], "world2");
+        assert_eq!(env["C"], "world2");
+        assert_eq!(env["D"], "${A}");
+        assert_eq!(env["E"], "${UNSET}");
+    }
+
+    #[test]
+    fn later_keys_are_not_used() {
+        let env = env("A=${B}\nB=1\n");
+        assert_eq!(env["A"], "${B}");
+    }
+
+    #[test]
+    fn invalid_env_files() {
+        for (contents, message) in [
+            ("A='x\n", "Unterminated quote on env file line 1."),
+            ("A=1\nB=\"x\n", "Unterminated quote on env file line 2."),
+            ("A=1\nB\n", "Invalid env file line 2, expected KEY=VALUE."),
+        ] {
+            let e = parse_env_file(contents, &vars).unwrap_err();
+            assert_eq!(e.to_string(), message, "{contents:?}");
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Sproc file watching (restarting services when their files change)
+use glob::Pattern;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::hooks::{self, Hook};
+use crate::logs::ServiceLogs;
+use crate::model::{Service, ServiceState, ServicesConfiguration};
+use crate::state::StateStore;
+
+/// How often (in milliseconds) watched files are checked for changes
+const POLL_INTERVAL: u64 = 500;
+
+/// Get the modification time of every file a service watches
+///
+/// Patterns are relative to the service's working directory, and directories they match are
+/// watched recursively. Files matching a `watch_ignore` pattern are skipped.
+pub fn snapshot(service: &Service) -> HashMap<PathBuf, SystemTime> {
+    let dir = Path::new(&service.working_directory);
+    let ignore: Vec<Pattern> = service
+        .watch_ignore
+        .iter()
+        .filter_map(|p| Pattern::new(p).ok())
+        .collect();
+
+    let mut files = HashMap::new();
+
+    let mut add = |path: PathBuf| {
+        let relative = path.strip_prefix(dir).unwrap_or(&path);
+
+        if ignore
+            .iter()
+            .any(|i| i.matches_path(relative) || i.matches_path(&path))
+        {
+            return;
+        }
+
+        if let Ok(m) = std::fs::metadata(&path) {
+            if let Ok(modified) = m.modified() {
+                files.insert(path, modified);
+            }
+        }
+    };
+
+    for pattern in &service.watch {
+        let full = dir.join(pattern);
+
+        let paths = match glob::glob(&full.to_string_lossy()) {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+
+        for path in paths.filter_map(|p| p.ok()) {
+            if !path.is_dir() {
+                add(path);
+                continue;
+            }
+
+            if let Ok(inside) = glob::glob(&path.join("**/*").to_string_lossy()) {
+                for path in inside.filter_map(|p| p.ok()).filter(|p| p.is_file()) {
+                    add(path);
+                }
+            }
+        }
+    }
+
+    files
+}
+
+/// Get a file that was changed, created or removed between two snapshots
+fn changed(
+    old: &HashMap<PathBuf, SystemTime>,
+    new: &HashMap<PathBuf, SystemTime>,
+) -> Option<PathBuf> {
+    for (path, modified) in new {
+        if old.get(path) != Some(modified) {
+            return Some(path.to_owned());
+        }
+    }
+
+    old.keys().find(|p| !new.contains_key(*p)).cloned()
+}
+
+/// If a service process is still running (and isn't being stopped)
+fn is_running(name: &String, pid: u32) -> bool {
+    match StateStore::get(name) {
+        Some(s) => (s.pid == pid) && s.state.is_active() && (s.state != ServiceState::Stopping),
+        None => false,
+    }
+}
+
+/// Read the (resolved) configuration of a service in a blocking task
+async fn service_blocking(name: &str) -> Option<Service> {
+    let name = name.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        let config = ServicesConfiguration::get_config();
+        config.service(&name)?.resolve(&config.vars).ok()
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Take a [`snapshot`] of the files of a service process in a blocking task (`None` if the
+/// process is no longer running)
+async fn snapshot_blocking(
+    name: &str,
+    pid: u32,
+    service: &Service,
+) -> Option<HashMap<PathBuf, SystemTime>> {
+    let name = name.to_owned();
+    let service = service.clone();
+
+    tokio::task::spawn_blocking(move || is_running(&name, pid).then(|| snapshot(&service)))
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Watch the files of a service process until it exits
+///
+/// Once files change (and stop changing for `watch_debounce` milliseconds), `restart` is set and
+/// the process is stopped gracefully (running its `pre_stop` hooks), so it can be restarted.
+/// Reading the configuration and taking snapshots is done in blocking tasks.
+pub async fn monitor(name: String, pid: u32, restart: Arc<AtomicBool>) {
+    let mut files = None;
+
+    loop {
+        // pull config
+        let service = match service_blocking(&name).await {
+            Some(s) => s,
+            None => return,
+        };
+
+        if service.watch.is_empty() {
+            return;
+        }
+
+        let previous = match files {
+            Some(ref f) => f,
+            None => match snapshot_blocking(&name, pid, &service).await {
+                Some(f) => files.insert(f),
+                None => return,
+            },
+        };
+
+        tokio::time::sleep(Duration::from_millis(POLL_INTERVAL)).await;
+
+        let mut current = match snapshot_blocking(&name, pid, &service).await {
+            Some(f) => f,
+            None => return,
+        };
+
+        let path = match changed(previous, &current) {
+            Some(p) => p,
+            None => continue,
+        };
+
+        // wait for changes to settle (a rebuild can write files for a while)
+        loop {
+            tokio::time::sleep(Duration::from_millis(service.watch_debounce)).await;
+
+            let next = match snapshot_blocking(&name, pid, &service).await {
+                Some(f) => f,
+                None => return,
+            };
+
+            if next == current {
+                break;
+            }
+
+            current = next;
+        }
+
+        // stop the process so it is restarted
+        println!(
+            "info: restarting service \"{name}\" ({} changed)",
+            path.display()
+        );
+
+        ServiceLogs::new(&name).event(&format!(
+            "restarting because a watched file changed ({})",
+            path.display()
+        ));
+
+        restart.store(true, Ordering::SeqCst);
+
+        let res = tokio::task::spawn_blocking(move || {
+            hooks::run_or_warn(&name, &service, Hook::PreStop);
+            Service::stop_process(pid, &service)
+        })
+        .await;
+
+        if let Ok(Err(e)) = res {
+            println!("warn: failed to stop service for restart: {e}");
+        }
+
+        return;
+    }
+}
@@ -5,10 +5,24 @@ use axum::Router;
 #[tokio::main]
 pub async fn main() {
     let config = sproc::model::ServicesConfiguration::get_config();
+    sproc::server::start_tasks();
+
+    // bring back the services that were running before the server stopped
+    tokio::task::spawn(sproc::server::resurrect());
 
     // create app
     let app = Router::new().nest_service("/api/sproc", sproc::server::sproc_api(config.clone()));
 
+    // local control through the unix socket
+    let socket = config.server.socket_path();
+    let socket_app = app.clone();
+
+    tokio::task::spawn(async move {
+        if let Err(e) = sproc::socket::serve(&socket, socket_app).await {
+            println!("warn: failed to listen on unix socket: {e}");
+        }
+    });
+
     let listener = tokio::net::TcpListener::bind(format!("127.0.0.1:{}", config.server.port))
         .await
         .unwrap();
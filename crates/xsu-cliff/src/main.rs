@@ -356,6 +356,10 @@ async fn profile_redirect(Path(username): Path<String>) -> impl IntoResponse {
 #[tokio::main]
 pub async fn main() {
     let config = sproc::model::ServicesConfiguration::get_config();
+    sproc::server::start_tasks();
+
+    // bring back the services that were running before the server stopped
+    tokio::task::spawn(sproc::server::resurrect());
 
     let home = std::env::var("HOME").expect("failed to read $HOME");
     let static_dir = format!("{home}/.config/xsu-apps/sproc/static");